@@ -0,0 +1,81 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Measures the time and peak heap memory of a run over one large .vrcset file, with `buffer_writes` on and off. The
+//! difference in peak memory is the cost of buffering. Run with `cargo bench --bench buffer_writes`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use hooligan::config::Config;
+use hooligan::local_player_moderations::{Line, Value};
+use hooligan::logging::LogFile;
+use hooligan::run::{self, Options};
+
+/// The system allocator, keeping track of how many bytes are allocated and the most there have been at once
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let dir = env::temp_dir().join(format!("hooligan-bench-buffer-{}", process::id()));
+    for entries in [10_000, 100_000, 1_000_000] {
+        let vrcset: String = (0..entries)
+            .map(|user: u64| Line::new(format!("usr_{user:08x}-0000-0000-0000-{user:012x}"), Value::Hide).serialize())
+            .collect();
+        let file_bytes = vrcset.len();
+        let (unbuffered_time, unbuffered_peak) = measure(dir.as_path(), &vrcset, false);
+        let (buffered_time, buffered_peak) = measure(dir.as_path(), &vrcset, true);
+        drop(vrcset);
+        let extra = u64::try_from(buffered_peak.saturating_sub(unbuffered_peak)).unwrap();
+        println!("{entries:>9} entries ({file_bytes} bytes): unbuffered {unbuffered_time:>10.1?} {unbuffered_peak:>11} bytes peak  \
+                  buffered {buffered_time:>10.1?} {buffered_peak:>11} bytes peak  (+{extra} bytes, {} per entry)",
+                 extra / entries);
+    }
+}
+
+/// Time one run over a fresh copy of `vrcset` with no history, returning how long it took and its peak heap usage
+fn measure(dir: &Path, vrcset: &str, buffer_writes: bool) -> (Duration, usize) {
+    let _ = fs::remove_dir_all(dir);
+    let moderations_dir = dir.join("moderations");
+    let history_dir = dir.join("history");
+    fs::create_dir_all(moderations_dir.as_path()).unwrap();
+    fs::write(moderations_dir.join("a.vrcset"), vrcset).unwrap();
+
+    let mut config = Config::default();
+    config.buffer_writes = buffer_writes;
+    let options = Options::default();
+    let mut log = LogFile::new(io::sink());
+    let baseline = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    let start = Instant::now();
+    run::run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &options, &mut log).unwrap();
+    let elapsed = start.elapsed();
+    let peak = PEAK.load(Ordering::Relaxed) - baseline;
+    let _ = fs::remove_dir_all(dir);
+    (elapsed, peak)
+}
@@ -0,0 +1,53 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Times [`transaction::read_log`] against [`transaction::read_log_for_keys`] on generated histories of increasing size.
+//! Run with `cargo bench --bench read_log`.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::process;
+use std::time::{Duration, Instant};
+
+use hooligan::config::Config;
+use hooligan::current_timestamp;
+use hooligan::transaction::{self, Transaction, Value};
+
+/// how many users the vrcset file is assumed to hold
+const KEYS: usize = 200;
+/// each measurement is the fastest of this many attempts
+const ATTEMPTS: u32 = 5;
+
+fn main() {
+    let config = Config::default();
+    let now = current_timestamp();
+    let dir = env::temp_dir().join(format!("hooligan-bench-{}", process::id()));
+    fs::create_dir_all(dir.as_path()).unwrap();
+    for users in [1_000, 10_000, 100_000, 1_000_000] {
+        let path = dir.join(format!("{users}.history"));
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path.as_path()).unwrap();
+        let transactions = (0..users)
+            .flat_map(|user| [Value::ManualShow, Value::AutoReset].map(|value| Transaction::new(format!("usr_{user:08}"), value, now)))
+            .collect();
+        transaction::write_log(&file, transactions).unwrap();
+
+        let keys: HashSet<String> = (0..users).step_by(users / KEYS).map(|user| format!("usr_{user:08}")).collect();
+        let all = fastest(|| transaction::read_log(&file, &config, now, drop).unwrap().len());
+        let filtered = fastest(|| transaction::read_log_for_keys(&file, &keys, &config, now, drop).unwrap().len());
+        println!("{users:>9} users: read_log {all:>10.1?}  read_log_for_keys {filtered:>10.1?}");
+    }
+    let _ = fs::remove_dir_all(dir);
+}
+
+fn fastest<F: FnMut() -> usize>(mut f: F) -> Duration {
+    (0..ATTEMPTS)
+        .map(|_| {
+            let start = Instant::now();
+            assert_ne!(f(), 0);
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
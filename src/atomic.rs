@@ -0,0 +1,137 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Crash-safe file replacement. New contents are written to a sibling `.tmp` file which is only renamed over the
+//! original once it is complete. The previous contents are kept as a sibling `.bak` file until the next replacement.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+const TMP_EXTENSION: &str = ".tmp";
+const BAK_EXTENSION: &str = ".bak";
+
+/// Replace the file at `path` with whatever `write` writes, creating it if it doesn't exist yet. If `write` fails, the
+/// original is left untouched. The file given to `write` can be read back too.
+pub fn replace_file<F: FnOnce(&File) -> Result<(), Error>>(path: &Path, write: F) -> Result<(), Error> {
+    let tmp_path = sibling(path, TMP_EXTENSION);
+    let tmp_file = {
+        let mut open_options = OpenOptions::new();
+        open_options.read(true);
+        open_options.write(true);
+        open_options.create(true);
+        open_options.truncate(true);
+        open_options.open(tmp_path.as_path())
+    };
+    let result = tmp_file.map_err(Error::Io)
+        .and_then(|tmp_file| {
+            write(&tmp_file)?;
+            tmp_file.sync_all().map_err(Error::Io)
+        });
+    if let Err(e) = result {
+        let _ = fs::remove_file(tmp_path); // best effort; recover() also cleans this up on the next run
+        return Err(e);
+    }
+
+    match fs::copy(path, sibling(path, BAK_EXTENSION)) {
+        Ok(_) => {}
+        // nothing to back up the first time a file is written
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(Error::Io(e)),
+    }
+    fs::rename(tmp_path, path).map_err(Error::Io)
+}
+
+/// What [`recover`] cleaned up after a crashed run
+#[derive(Debug, Default)]
+pub struct Recovery {
+    /// incomplete `.tmp` files that were deleted
+    pub discarded: Vec<PathBuf>,
+    /// files that were missing and restored from their `.bak`
+    pub restored: Vec<PathBuf>,
+}
+
+/// Clean up after a crashed run: discard leftover `.tmp` files for files with `extension`, and restore any such file
+/// that has gone missing from its `.bak`.
+pub fn recover(dir: &Path, extension: &str) -> Result<Recovery, Error> {
+    let mut recovery = Recovery::default();
+    for dir_entry in fs::read_dir(dir).map_err(Error::Io)? {
+        let path = dir_entry.map_err(Error::Io)?.path();
+        let Some(filename) = path.file_name().and_then(|filename| filename.to_str()) else {
+            continue;
+        };
+        if filename.strip_suffix(TMP_EXTENSION).is_some_and(|original| original.ends_with(extension)) {
+            fs::remove_file(path.as_path()).map_err(Error::Io)?;
+            recovery.discarded.push(path);
+        } else if let Some(original) = filename.strip_suffix(BAK_EXTENSION).filter(|original| original.ends_with(extension)) {
+            let original_path = path.with_file_name(original);
+            if !original_path.exists() {
+                fs::copy(path.as_path(), original_path.as_path()).map_err(Error::Io)?;
+                recovery.restored.push(original_path);
+            }
+        }
+    }
+    Ok(recovery)
+}
+
+/// `path` with `extension` tacked onto the end of its filename
+pub(crate) fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut filename = path.file_name().map(ToOwned::to_owned).unwrap_or_default();
+    filename.push(extension);
+    path.with_file_name(filename)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn test_replace_file() {
+        let dir = TestDir::new("atomic-replace");
+        let path = dir.join("a.vrcset");
+        fs::write(path.as_path(), "old").unwrap();
+
+        replace_file(path.as_path(), |mut file| file.write_all(b"new").map_err(Error::Io)).unwrap();
+        assert_eq!(fs::read_to_string(path.as_path()).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.join("a.vrcset.bak")).unwrap(), "old");
+        assert!(!dir.join("a.vrcset.tmp").exists());
+    }
+
+    #[test]
+    fn test_replace_file_failure() {
+        let dir = TestDir::new("atomic-failure");
+        let path = dir.join("a.vrcset");
+        fs::write(path.as_path(), "old").unwrap();
+
+        let result = replace_file(path.as_path(), |mut file| {
+            file.write_all(b"partial").map_err(Error::Io)?;
+            Err(Error::Io(io::Error::other("simulated failure")))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(path.as_path()).unwrap(), "old");
+        assert!(!dir.join("a.vrcset.tmp").exists());
+    }
+
+    #[test]
+    fn test_recover() {
+        let dir = TestDir::new("atomic-recover");
+        fs::write(dir.join("a.vrcset.tmp"), "partial").unwrap();
+        fs::write(dir.join("a.vrcset"), "current").unwrap();
+        fs::write(dir.join("b.vrcset.bak"), "backup").unwrap();
+        fs::write(dir.join("c.txt.tmp"), "unrelated").unwrap();
+
+        let recovery = recover(dir.as_path(), ".vrcset").unwrap();
+        assert_eq!(recovery.discarded, vec![dir.join("a.vrcset.tmp")]);
+        assert_eq!(recovery.restored, vec![dir.join("b.vrcset")]);
+        assert!(!dir.join("a.vrcset.tmp").exists());
+        assert_eq!(fs::read_to_string(dir.join("a.vrcset")).unwrap(), "current");
+        assert_eq!(fs::read_to_string(dir.join("b.vrcset")).unwrap(), "backup");
+        assert!(dir.join("c.txt.tmp").exists());
+    }
+}
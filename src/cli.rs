@@ -0,0 +1,405 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Command line argument handling
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+const EXPORT_ALLOWLIST: &str = "--export-allowlist";
+const CONFIRM: &str = "--confirm";
+const DRY_RUN: &str = "--dry-run";
+const DIFF: &str = "--diff";
+const EXTERNAL_CHANGES: &str = "--external-changes";
+const MODERATIONS_DIR: &str = "--moderations-dir";
+const NO_LAUNCH: &str = "--no-launch";
+const PROFILE: &str = "--profile";
+const RESET_ALL: &str = "--reset-all";
+const SET: &str = "--set";
+const THRESHOLD: &str = "--threshold";
+const TIMELINE: &str = "--timeline";
+const JSON: &str = "--json";
+const YES: &str = "--yes";
+const END_OF_ARGS: &str = "--";
+/// subcommands are only recognized as the very first argument
+const STATUS: &str = "status";
+const UNDO: &str = "undo";
+const HISTORY: &str = "history";
+const EXPORT_CSV: &str = "export-csv";
+const IMPORT_HIDE: &str = "import-hide";
+const RESET: &str = "reset";
+/// like subcommands, these are only recognized as the very first argument so they can't be mistaken for part of a command
+const HELP: &str = "--help";
+const VERSION: &str = "--version";
+
+/// Hooligan's own arguments come first. Everything after them is a command to launch.
+#[derive(Debug, Default)]
+pub struct Args {
+    /// print usage instead of doing a normal run
+    pub help: bool,
+    /// print the version instead of doing a normal run
+    pub version: bool,
+    /// print the users that are currently sticky instead of doing a normal run
+    pub status: bool,
+    /// reverse the changes made by the last run instead of doing a normal run
+    pub undo: bool,
+    /// print every transaction for this user in each history file instead of doing a normal run
+    pub history: Option<String>,
+    /// write every user's show count in each history file to this CSV file instead of doing a normal run
+    pub export_csv: Option<PathBuf>,
+    /// hide every user listed in this file in each .vrcset file instead of doing a normal run
+    pub import_hide: Option<PathBuf>,
+    /// forget everything known about this user instead of doing a normal run
+    pub reset: Option<String>,
+    /// write the currently sticky users to this file instead of doing a normal run
+    pub export_allowlist: Option<PathBuf>,
+    /// report changes made to this .vrcset file outside of hooligan instead of doing a normal run
+    pub external_changes: Option<String>,
+    /// show log lines and history for this .vrcset file in chronological order instead of doing a normal run
+    pub timeline: Option<String>,
+    /// read-only commands write JSON instead of their human-readable format
+    pub json: bool,
+    /// when run from a terminal, ask before modifying each file
+    pub confirm: bool,
+    /// log what would change, but leave the .vrcset files and history untouched
+    pub dry_run: bool,
+    /// print which users were removed, retained, and shown in each file
+    pub diff: bool,
+    /// look for .vrcset files here instead of VRChat's LocalPlayerModerations folder
+    pub moderations_dir: Option<PathBuf>,
+    /// process files but never launch the command, even if one was provided
+    pub no_launch: bool,
+    /// forget every user's show count instead of doing a normal run
+    pub reset_all: bool,
+    /// confirms a destructive command such as `--reset-all`
+    pub yes: bool,
+    /// use a separate set of config, data, and log directories
+    pub profile: Option<String>,
+    /// `auto_hide_threshold` for this run only
+    pub threshold: Option<u32>,
+    /// `key=value` config overrides for this run only
+    pub config_overrides: Vec<String>,
+    /// command to launch after processing
+    pub command: Vec<OsString>,
+}
+
+impl Args {
+    /// Parse arguments, not including the path to this executable
+    pub fn parse<I: IntoIterator<Item=OsString>>(args: I) -> Result<Self, Error> {
+        let mut args = args.into_iter().peekable();
+        let mut parsed = Self::default();
+        if args.next_if(|arg| arg == HELP).is_some() {
+            parsed.help = true;
+            return Ok(parsed);
+        } else if args.next_if(|arg| arg == VERSION).is_some() {
+            parsed.version = true;
+            return Ok(parsed);
+        } else if args.next_if(|arg| arg == STATUS).is_some() {
+            parsed.status = true;
+        } else if args.next_if(|arg| arg == UNDO).is_some() {
+            parsed.undo = true;
+        } else if args.next_if(|arg| arg == HISTORY).is_some() {
+            let key = args.next().ok_or(Error::MissingValue(HISTORY))?;
+            parsed.history = Some(key.into_string().map_err(Error::NotUnicode)?);
+        } else if args.next_if(|arg| arg == EXPORT_CSV).is_some() {
+            let path = args.next().ok_or(Error::MissingValue(EXPORT_CSV))?;
+            parsed.export_csv = Some(PathBuf::from(path));
+        } else if args.next_if(|arg| arg == IMPORT_HIDE).is_some() {
+            let path = args.next().ok_or(Error::MissingValue(IMPORT_HIDE))?;
+            parsed.import_hide = Some(PathBuf::from(path));
+        } else if args.next_if(|arg| arg == RESET).is_some() {
+            let key = args.next().ok_or(Error::MissingValue(RESET))?;
+            parsed.reset = Some(key.into_string().map_err(Error::NotUnicode)?);
+        }
+        while let Some(arg) = args.next() {
+            if arg == EXPORT_ALLOWLIST {
+                let path = args.next().ok_or(Error::MissingValue(EXPORT_ALLOWLIST))?;
+                parsed.export_allowlist = Some(PathBuf::from(path));
+            } else if arg == EXTERNAL_CHANGES {
+                let name = args.next().ok_or(Error::MissingValue(EXTERNAL_CHANGES))?;
+                parsed.external_changes = Some(name.into_string().map_err(Error::NotUnicode)?);
+            } else if arg == CONFIRM {
+                parsed.confirm = true;
+            } else if arg == DRY_RUN {
+                parsed.dry_run = true;
+            } else if arg == DIFF {
+                parsed.diff = true;
+            } else if arg == TIMELINE {
+                let name = args.next().ok_or(Error::MissingValue(TIMELINE))?;
+                parsed.timeline = Some(name.into_string().map_err(Error::NotUnicode)?);
+            } else if arg == MODERATIONS_DIR {
+                let path = args.next().ok_or(Error::MissingValue(MODERATIONS_DIR))?;
+                parsed.moderations_dir = Some(PathBuf::from(path));
+            } else if arg == NO_LAUNCH {
+                parsed.no_launch = true;
+            } else if arg == RESET_ALL {
+                parsed.reset_all = true;
+            } else if arg == YES {
+                parsed.yes = true;
+            } else if arg == JSON {
+                parsed.json = true;
+            } else if arg == PROFILE {
+                let profile = args.next().ok_or(Error::MissingValue(PROFILE))?;
+                let profile = profile.into_string().map_err(Error::NotUnicode)?;
+                // the profile becomes part of a directory name
+                if profile.is_empty() || !profile.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                    return Err(Error::BadProfile(profile));
+                }
+                parsed.profile = Some(profile);
+            } else if arg == THRESHOLD {
+                let threshold = args.next().ok_or(Error::MissingValue(THRESHOLD))?;
+                let threshold = threshold.into_string().map_err(Error::NotUnicode)?;
+                parsed.threshold = Some(threshold.parse().map_err(|_| Error::BadInt(THRESHOLD, threshold))?);
+            } else if arg == SET {
+                let config_override = args.next().ok_or(Error::MissingValue(SET))?;
+                parsed.config_overrides.push(config_override.into_string().map_err(Error::NotUnicode)?);
+            } else if arg == END_OF_ARGS {
+                // explicit separator, so everything after it is the command even if it looks like one of our flags
+                parsed.command.extend(args);
+                break;
+            } else {
+                // not one of ours, so this and everything after it is the command
+                parsed.command.push(arg);
+                parsed.command.extend(args);
+                break;
+            }
+        }
+        if parsed.reset_all && !parsed.yes {
+            return Err(Error::MissingYes(RESET_ALL));
+        }
+        Ok(parsed)
+    }
+}
+
+/// Usage text for `--help`, listing every subcommand, flag, and config key
+pub fn usage() -> String {
+    let mut usage = format!("\
+usage: hooligan [SUBCOMMAND] [OPTIONS] [--] [COMMAND...]
+
+Cleans up VRChat's LocalPlayerModerations, then launches COMMAND. Hooligan's own arguments must come first: the first
+argument it doesn't recognize, or everything after {END_OF_ARGS}, is the command to launch.
+
+Subcommands, only recognized as the first argument:
+  {STATUS}                    print the users that are currently sticky
+  {UNDO}                      reverse the changes made by the last run
+  {HISTORY} <USER>            print every transaction for a user
+  {EXPORT_CSV} <FILE>         write every user's show count to a CSV file
+  {IMPORT_HIDE} <FILE>        hide every user listed in a file, one per line
+  {RESET} <USER>              forget everything known about a user
+  {HELP}                    print this text
+  {VERSION}                 print the version
+
+Options:
+  {EXPORT_ALLOWLIST} <FILE> write the currently sticky users to a file
+  {EXTERNAL_CHANGES} <NAME> report changes made to a .vrcset file outside of hooligan
+  {TIMELINE} <NAME>         show log lines and history for a .vrcset file in order
+  {JSON}                    write JSON from the commands above
+  {CONFIRM}                 ask before modifying each file
+  {DRY_RUN}                 log what would change without changing anything
+  {DIFF}                    print which users were removed, retained, and shown
+  {MODERATIONS_DIR} <DIR>   look for .vrcset files in DIR
+  {NO_LAUNCH}               don't launch COMMAND
+  {RESET_ALL} {YES}         forget every user's show count
+  {PROFILE} <NAME>          use a separate config, history, and logs
+  {THRESHOLD} <N>           auto_hide_threshold for this run only
+  {SET} <KEY=VALUE>         change a config key for this run only
+
+Config keys, set in config.props or with {SET}:
+");
+    for key in Config::key_names() {
+        usage.push_str(&format!("  {key}\n"));
+    }
+    usage.push_str("  pin                       may be repeated, once per user to never auto-hide\n");
+    usage.push_str("  exempt_prefix             may be repeated, never auto-hide users whose ID starts with it\n");
+    usage.push_str("  threshold.<USER>          auto_hide_threshold for one user\n");
+    usage
+}
+
+#[allow(dead_code)] // lint misses usage in debug printing this error
+#[derive(Debug)]
+pub enum Error {
+    /// a flag was missing its value
+    MissingValue(&'static str),
+    /// a flag value needed to be valid unicode
+    NotUnicode(OsString),
+    /// a destructive flag was passed without `--yes`
+    MissingYes(&'static str),
+    /// profile names may only contain ASCII letters, digits, `-`, and `_`
+    BadProfile(String),
+    /// a flag value needed to be a non-negative integer
+    BadInt(&'static str, String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, Error> {
+        Args::parse(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn test_args_command() {
+        let actual = parse(&["launch.exe", "--no-vr"]).unwrap();
+        assert_eq!(actual.export_allowlist, None);
+        assert_eq!(actual.command, vec![OsString::from("launch.exe"), OsString::from("--no-vr")]);
+    }
+
+    #[test]
+    fn test_args_export_allowlist() {
+        let actual = parse(&["--export-allowlist", "allowlist.txt", "--json"]).unwrap();
+        assert_eq!(actual.export_allowlist, Some(PathBuf::from("allowlist.txt")));
+        assert!(actual.json);
+        assert!(actual.command.is_empty());
+    }
+
+    #[test]
+    fn test_args_no_launch() {
+        let actual = parse(&["--no-launch", "launch.exe", "--no-vr"]).unwrap();
+        assert!(actual.no_launch);
+        assert_eq!(actual.command, vec![OsString::from("launch.exe"), OsString::from("--no-vr")]);
+    }
+
+    #[test]
+    fn test_args_dry_run() {
+        let actual = parse(&["--dry-run", "launch.exe"]).unwrap();
+        assert!(actual.dry_run);
+        assert!(!actual.diff);
+        let actual = parse(&["--dry-run", "--diff", "launch.exe"]).unwrap();
+        assert!(actual.dry_run && actual.diff);
+        assert!(!actual.no_launch);
+        assert_eq!(actual.command, vec![OsString::from("launch.exe")]);
+    }
+
+    #[test]
+    fn test_args_reset_all() {
+        assert!(matches!(parse(&["--reset-all"]), Err(Error::MissingYes(RESET_ALL))));
+        let actual = parse(&["--reset-all", "--yes"]).unwrap();
+        assert!(actual.reset_all);
+        assert!(actual.command.is_empty());
+    }
+
+    #[test]
+    fn test_args_status() {
+        let actual = parse(&["status", "--profile", "alt"]).unwrap();
+        assert!(actual.status);
+        assert_eq!(actual.profile.as_deref(), Some("alt"));
+        assert!(actual.command.is_empty());
+
+        // only a subcommand in first position
+        let actual = parse(&["--no-launch", "status"]).unwrap();
+        assert!(!actual.status);
+        assert_eq!(actual.command, vec![OsString::from("status")]);
+    }
+
+    #[test]
+    fn test_args_undo() {
+        let actual = parse(&["undo"]).unwrap();
+        assert!(actual.undo);
+        assert!(!actual.status);
+        assert!(actual.command.is_empty());
+
+        let actual = parse(&["status", "undo"]).unwrap();
+        assert!(!actual.undo);
+        assert_eq!(actual.command, vec![OsString::from("undo")]);
+    }
+
+    #[test]
+    fn test_args_history() {
+        let actual = parse(&["history", "usr_a", "--no-launch"]).unwrap();
+        assert_eq!(actual.history.as_deref(), Some("usr_a"));
+        assert!(actual.no_launch);
+        assert!(actual.command.is_empty());
+
+        assert!(matches!(parse(&["history"]), Err(Error::MissingValue(HISTORY))));
+    }
+
+    #[test]
+    fn test_args_export_csv() {
+        let actual = parse(&["export-csv", "counts.csv"]).unwrap();
+        assert_eq!(actual.export_csv, Some(PathBuf::from("counts.csv")));
+        assert!(actual.command.is_empty());
+
+        assert!(matches!(parse(&["export-csv"]), Err(Error::MissingValue(EXPORT_CSV))));
+    }
+
+    #[test]
+    fn test_args_help_version() {
+        assert!(parse(&["--help"]).unwrap().help);
+        assert!(parse(&["--version"]).unwrap().version);
+        // only as the first argument, so a launched command keeps its own
+        let actual = parse(&["launch.exe", "--help"]).unwrap();
+        assert!(!actual.help);
+        assert_eq!(actual.command, vec![OsString::from("launch.exe"), OsString::from("--help")]);
+        let actual = parse(&["--no-launch", "--version"]).unwrap();
+        assert!(!actual.version);
+        assert_eq!(actual.command, vec![OsString::from("--version")]);
+    }
+
+    #[test]
+    fn test_args_reset() {
+        let actual = parse(&["reset", "usr_a", "--no-launch"]).unwrap();
+        assert_eq!(actual.reset.as_deref(), Some("usr_a"));
+        assert!(actual.no_launch);
+        assert!(actual.command.is_empty());
+
+        assert!(matches!(parse(&["reset"]), Err(Error::MissingValue(RESET))));
+        // only as the first argument
+        assert_eq!(parse(&["launch.exe", "reset", "usr_a"]).unwrap().reset, None);
+    }
+
+    #[test]
+    fn test_args_import_hide() {
+        let actual = parse(&["import-hide", "hides.txt"]).unwrap();
+        assert_eq!(actual.import_hide, Some(PathBuf::from("hides.txt")));
+        assert!(actual.command.is_empty());
+
+        assert!(matches!(parse(&["import-hide"]), Err(Error::MissingValue(IMPORT_HIDE))));
+    }
+
+    #[test]
+    fn test_args_threshold() {
+        let actual = parse(&["--threshold", "5", "--no-launch", "launch.exe", "--threshold", "2"]).unwrap();
+        assert_eq!(actual.threshold, Some(5));
+        assert!(actual.no_launch);
+        assert_eq!(actual.command, vec![OsString::from("launch.exe"), OsString::from("--threshold"), OsString::from("2")]);
+
+        assert!(matches!(parse(&["--threshold"]), Err(Error::MissingValue(THRESHOLD))));
+        assert!(matches!(parse(&["--threshold", "-1"]), Err(Error::BadInt(THRESHOLD, value)) if value == "-1"));
+    }
+
+    #[test]
+    fn test_args_moderations_dir() {
+        let dir = env::temp_dir().join("hooligan moderations");
+        let actual = Args::parse([OsString::from(MODERATIONS_DIR), dir.clone().into_os_string(), OsString::from("launch.exe"), OsString::from(MODERATIONS_DIR)]).unwrap();
+        assert_eq!(actual.moderations_dir, Some(dir));
+        assert_eq!(actual.command, vec![OsString::from("launch.exe"), OsString::from(MODERATIONS_DIR)]);
+        assert!(matches!(parse(&[MODERATIONS_DIR]), Err(Error::MissingValue(MODERATIONS_DIR))));
+    }
+
+    #[test]
+    fn test_args_set() {
+        let actual = parse(&["--set", "auto_hide_threshold=5", "--set", "track_stats=true", "--", "--no-launch"]).unwrap();
+        assert_eq!(actual.config_overrides, vec!["auto_hide_threshold=5".to_string(), "track_stats=true".to_string()]);
+        assert!(!actual.no_launch);
+        assert_eq!(actual.command, vec![OsString::from("--no-launch")]);
+    }
+
+    #[test]
+    fn test_args_profile() {
+        let actual = parse(&["--profile", "alt", "launch.exe"]).unwrap();
+        assert_eq!(actual.profile.as_deref(), Some("alt"));
+        assert_eq!(actual.command, vec![OsString::from("launch.exe")]);
+        assert!(matches!(parse(&["--profile", "../alt"]), Err(Error::BadProfile(_))));
+    }
+
+    #[test]
+    fn test_args_export_allowlist_missing_value() {
+        assert!(matches!(parse(&["--export-allowlist"]), Err(Error::MissingValue(EXPORT_ALLOWLIST))));
+    }
+}
@@ -2,50 +2,466 @@
 // See LICENSE file for full text.
 // Copyright © 2024 Michael Ripley
 
+use std::collections::{HashMap, HashSet};
+use std::{env, fs};
 use std::fs::File;
 use std::io;
 use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
+use std::str::FromStr;
 
+use crate::glob;
+use crate::logging;
+use crate::logging::{Format, Level, Timestamp};
+
+/// environment variable that, when set, stops a default config file from being created where there is none
+pub const NO_WRITE_CONFIG_VAR: &str = "HOOLIGAN_NO_WRITE_CONFIG";
+/// the version of the config format a file was written in. Absent from files written before versioning, which are version 0.
+const CONFIG_VERSION: &str = "config_version";
+/// the config format written by this build. Bump this and add a step to [`Config::migrate`] when the meaning of an
+/// existing key changes.
+pub const CURRENT_VERSION: u32 = 1;
 const AUTO_HIDE_THRESHOLD: &str = "auto_hide_threshold";
+/// prefix of per-user `auto_hide_threshold` overrides, e.g. `threshold.usr_6b683acd-31a6-495d-aa46-a73c1349f462=10`
+const USER_THRESHOLD_PREFIX: &str = "threshold.";
+/// may be repeated, once per pinned user
+const PIN: &str = "pin";
+/// may be repeated, once per prefix. Any user whose key starts with one is exempt from auto hide, like a pinned user.
+const EXEMPT_PREFIX: &str = "exempt_prefix";
+const TRACK_STATS: &str = "track_stats";
+const WARMUP_RUNS: &str = "warmup_runs";
+const INCLUDE_GLOB: &str = "include_glob";
+const EXCLUDE_GLOB: &str = "exclude_glob";
+const PRUNE_HIDES_AFTER_DAYS: &str = "prune_hides_after_days";
+const MAX_HISTORY_KEYS: &str = "max_history_keys";
+const IGNORE_UNKNOWN_TRANSACTIONS: &str = "ignore_unknown_transactions";
+const MAX_AUTO_SHOW_PER_RUN: &str = "max_auto_show_per_run";
+const BUFFER_WRITES: &str = "buffer_writes";
+const MAX_LINE_BYTES: &str = "max_line_bytes";
+const LAUNCH_RETRIES: &str = "launch_retries";
+const COMPACT_HISTORY_OVER_BYTES: &str = "compact_history_over_bytes";
+const LOG_LEVEL: &str = "log_level";
+const LOG_FORMAT: &str = "log_format";
+const DECAY_DAYS: &str = "decay_days";
+const RERUN_AFTER_EXIT: &str = "rerun_after_exit";
+const LOCK_TIMEOUT_SECS: &str = "lock_timeout_secs";
+const AUTO_PIN_AFTER_SHOWS: &str = "auto_pin_after_shows";
+const MAX_LOG_FILES: &str = "max_log_files";
+const MAX_LOG_FILE_SIZE_BYTES: &str = "max_log_file_size_bytes";
+const UNSTICK_AFTER_HIDES: &str = "unstick_after_hides";
+const LAUNCH_COMMAND: &str = "launch_command";
+const NOTIFY: &str = "notify";
+const STREAM_HISTORY_OVER_BYTES: &str = "stream_history_over_bytes";
+const LOG_TIMESTAMP: &str = "log_timestamp";
+const AUTO_HIDE_COOLDOWN_SECS: &str = "auto_hide_cooldown_secs";
 
 pub struct Config {
-    /// a user that has been manually shown this many times in a row is exempt from auto hide
+    /// the config format version this was read as
+    pub version: u32,
+    /// a user that has been manually shown this many times in a row is exempt from auto hide. 0 disables auto hide.
     pub auto_hide_threshold: u32,
+    /// keep lifetime statistics for each moderations file alongside its history
+    pub track_stats: bool,
+    /// for this many runs against a history file, only record manual changes and never auto reset or auto show
+    pub warmup_runs: u32,
+    /// only process moderations files whose name matches this glob
+    pub include_glob: Option<String>,
+    /// skip moderations files whose name matches this glob
+    pub exclude_glob: Option<String>,
+    /// remove hidden user entries that have had no activity for this many days. 0 disables pruning. Note that a pruned user's avatar will be shown again.
+    pub prune_hides_after_days: u32,
+    /// track at most this many distinct users when reading a history file before falling back to only tracking users in the .vrcset file. 0 means no limit.
+    pub max_history_keys: usize,
+    /// skip history transactions with values this version doesn't know about instead of failing
+    pub ignore_unknown_transactions: bool,
+    /// re-show at most this many users per file in one run after the threshold is lowered, preferring users with the most shows. The rest are re-shown on later runs. 0 means no limit.
+    pub max_auto_show_per_run: usize,
+    /// serialize a whole .vrcset file in memory and write it in one go, so a failed write is far less likely to leave a partial file. While writing, the buffer takes up to about twice the file's size: `cargo bench --bench buffer_writes` measured 67 to 162 extra bytes per entry. When false, lines are written one at a time.
+    pub buffer_writes: bool,
+    /// lines in a .vrcset file longer than this many bytes are treated as corrupt, and moved to a .quarantine file beside it when the file is rewritten. 0 means no limit.
+    pub max_line_bytes: usize,
+    /// retry launching the command this many times if it fails to start, waiting a little longer before each attempt
+    pub launch_retries: u32,
+    /// rewrite a history file as the fewest transactions that reproduce it once it grows past this many bytes. Individual past events are lost. 0 means never.
+    pub compact_history_over_bytes: u64,
+    /// how much detail goes in the log: error, info, or debug
+    pub log_level: Level,
+    /// text for `<timestamp>: <message>` lines, or json for one JSON object per line.
+    pub log_format: Format,
+    /// forget a user's shows once their history has been quiet for this many days, so they have to be shown up to the threshold again. 0 means shows are kept forever.
+    pub decay_days: u32,
+    /// wait for the launched command to exit, then clean up again. This keeps hooligan running for the whole session.
+    pub rerun_after_exit: bool,
+    /// keep retrying for this many seconds while another instance holds the lock. 0 gives up straight away.
+    pub lock_timeout_secs: u64,
+    /// pin a user in their history once they've been manually shown this many times, so they are never auto reset again until they are hidden or removed by hand. 0 means never.
+    pub auto_pin_after_shows: u32,
+    /// keep at most this many log files, deleting the oldest first
+    pub max_log_files: usize,
+    /// start another log file for the same day once the current one has grown past this many bytes
+    pub max_log_file_size_bytes: u64,
+    /// once a user has been manually hidden this many times without reaching their threshold in between, they are never auto shown and need twice as many shows to stick. 0 means never.
+    pub unstick_after_hides: u32,
+    /// command to launch when hooligan is given no command of its own, split on whitespace. Double quotes group a path containing spaces.
+    pub launch_command: Option<String>,
+    /// show a desktop notification summarizing each run. Only does anything on Windows.
+    pub notify: bool,
+    /// history files larger than this only have the users in their .vrcset file read into memory. Users missing from the .vrcset file aren't re-shown or reset for that run. 0 means never.
+    pub stream_history_over_bytes: u64,
+    /// how log lines are timestamped: epoch for unix seconds, or iso for an RFC 3339 date and time in the local time zone
+    pub log_timestamp: Timestamp,
+    /// once a user has been auto reset, keep retaining their shows instead of resetting them again until this many seconds have passed. 0 means no cooldown.
+    pub auto_hide_cooldown_secs: u64,
+    /// per-user overrides of `auto_hide_threshold`
+    pub user_thresholds: HashMap<String, u32>,
+    /// users that are never auto-hidden, no matter how many times they've been shown
+    pub pinned_users: HashSet<String>,
+    /// key prefixes of users that are never auto-hidden, kept as raw bytes so that checking a key doesn't allocate
+    pub exempt_prefixes: Vec<Box<[u8]>>,
+    /// original line structure of the loaded file, so that serializing doesn't drop comments or unrecognized keys
+    lines: Vec<Line>,
+}
+
+/// A single line of a config file
+enum Line {
+    /// the config version; its current value is written on serialize
+    Version,
+    /// a recognized key; its current value is written on serialize
+    Key(Key),
+    /// a per-user threshold override for this user; its current value is written on serialize
+    UserThreshold(String),
+    /// a pinned user; written back as long as they are still pinned
+    Pin(String),
+    /// an exempt prefix; written back as long as it is still exempt
+    ExemptPrefix(String),
+    /// a comment, blank line, or unrecognized key; written back verbatim
+    Verbatim(String),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Key {
+    AutoHideThreshold,
+    TrackStats,
+    WarmupRuns,
+    IncludeGlob,
+    ExcludeGlob,
+    PruneHidesAfterDays,
+    MaxHistoryKeys,
+    IgnoreUnknownTransactions,
+    MaxAutoShowPerRun,
+    BufferWrites,
+    MaxLineBytes,
+    LaunchRetries,
+    CompactHistoryOverBytes,
+    LogLevel,
+    LogFormat,
+    DecayDays,
+    RerunAfterExit,
+    LockTimeoutSecs,
+    AutoPinAfterShows,
+    MaxLogFiles,
+    MaxLogFileSizeBytes,
+    UnstickAfterHides,
+    LaunchCommand,
+    Notify,
+    StreamHistoryOverBytes,
+    LogTimestamp,
+    AutoHideCooldownSecs,
+}
+
+impl Key {
+    const ALL: [Self; 27] = [Self::AutoHideThreshold, Self::TrackStats, Self::WarmupRuns, Self::IncludeGlob, Self::ExcludeGlob, Self::PruneHidesAfterDays, Self::MaxHistoryKeys, Self::IgnoreUnknownTransactions, Self::MaxAutoShowPerRun, Self::BufferWrites, Self::MaxLineBytes, Self::LaunchRetries, Self::CompactHistoryOverBytes, Self::LogLevel, Self::LogFormat, Self::DecayDays, Self::RerunAfterExit, Self::LockTimeoutSecs, Self::AutoPinAfterShows, Self::MaxLogFiles, Self::MaxLogFileSizeBytes, Self::UnstickAfterHides, Self::LaunchCommand, Self::Notify, Self::StreamHistoryOverBytes, Self::LogTimestamp, Self::AutoHideCooldownSecs];
+
+    fn parse(key: &str) -> Option<Self> {
+        match key {
+            AUTO_HIDE_THRESHOLD => Some(Self::AutoHideThreshold),
+            TRACK_STATS => Some(Self::TrackStats),
+            WARMUP_RUNS => Some(Self::WarmupRuns),
+            INCLUDE_GLOB => Some(Self::IncludeGlob),
+            EXCLUDE_GLOB => Some(Self::ExcludeGlob),
+            PRUNE_HIDES_AFTER_DAYS => Some(Self::PruneHidesAfterDays),
+            MAX_HISTORY_KEYS => Some(Self::MaxHistoryKeys),
+            IGNORE_UNKNOWN_TRANSACTIONS => Some(Self::IgnoreUnknownTransactions),
+            MAX_AUTO_SHOW_PER_RUN => Some(Self::MaxAutoShowPerRun),
+            BUFFER_WRITES => Some(Self::BufferWrites),
+            MAX_LINE_BYTES => Some(Self::MaxLineBytes),
+            LAUNCH_RETRIES => Some(Self::LaunchRetries),
+            COMPACT_HISTORY_OVER_BYTES => Some(Self::CompactHistoryOverBytes),
+            LOG_LEVEL => Some(Self::LogLevel),
+            LOG_FORMAT => Some(Self::LogFormat),
+            DECAY_DAYS => Some(Self::DecayDays),
+            RERUN_AFTER_EXIT => Some(Self::RerunAfterExit),
+            LOCK_TIMEOUT_SECS => Some(Self::LockTimeoutSecs),
+            AUTO_PIN_AFTER_SHOWS => Some(Self::AutoPinAfterShows),
+            MAX_LOG_FILES => Some(Self::MaxLogFiles),
+            MAX_LOG_FILE_SIZE_BYTES => Some(Self::MaxLogFileSizeBytes),
+            UNSTICK_AFTER_HIDES => Some(Self::UnstickAfterHides),
+            LAUNCH_COMMAND => Some(Self::LaunchCommand),
+            NOTIFY => Some(Self::Notify),
+            STREAM_HISTORY_OVER_BYTES => Some(Self::StreamHistoryOverBytes),
+            LOG_TIMESTAMP => Some(Self::LogTimestamp),
+            AUTO_HIDE_COOLDOWN_SECS => Some(Self::AutoHideCooldownSecs),
+            _ => None,
+        }
+    }
+
+    const fn serialize(self) -> &'static str {
+        match self {
+            Self::AutoHideThreshold => AUTO_HIDE_THRESHOLD,
+            Self::TrackStats => TRACK_STATS,
+            Self::WarmupRuns => WARMUP_RUNS,
+            Self::IncludeGlob => INCLUDE_GLOB,
+            Self::ExcludeGlob => EXCLUDE_GLOB,
+            Self::PruneHidesAfterDays => PRUNE_HIDES_AFTER_DAYS,
+            Self::MaxHistoryKeys => MAX_HISTORY_KEYS,
+            Self::IgnoreUnknownTransactions => IGNORE_UNKNOWN_TRANSACTIONS,
+            Self::MaxAutoShowPerRun => MAX_AUTO_SHOW_PER_RUN,
+            Self::BufferWrites => BUFFER_WRITES,
+            Self::MaxLineBytes => MAX_LINE_BYTES,
+            Self::LaunchRetries => LAUNCH_RETRIES,
+            Self::CompactHistoryOverBytes => COMPACT_HISTORY_OVER_BYTES,
+            Self::LogLevel => LOG_LEVEL,
+            Self::LogFormat => LOG_FORMAT,
+            Self::DecayDays => DECAY_DAYS,
+            Self::RerunAfterExit => RERUN_AFTER_EXIT,
+            Self::LockTimeoutSecs => LOCK_TIMEOUT_SECS,
+            Self::AutoPinAfterShows => AUTO_PIN_AFTER_SHOWS,
+            Self::MaxLogFiles => MAX_LOG_FILES,
+            Self::MaxLogFileSizeBytes => MAX_LOG_FILE_SIZE_BYTES,
+            Self::UnstickAfterHides => UNSTICK_AFTER_HIDES,
+            Self::LaunchCommand => LAUNCH_COMMAND,
+            Self::Notify => NOTIFY,
+            Self::StreamHistoryOverBytes => STREAM_HISTORY_OVER_BYTES,
+            Self::LogTimestamp => LOG_TIMESTAMP,
+            Self::AutoHideCooldownSecs => AUTO_HIDE_COOLDOWN_SECS,
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            version: CURRENT_VERSION,
             auto_hide_threshold: 3,
+            track_stats: false,
+            warmup_runs: 0,
+            include_glob: None,
+            exclude_glob: None,
+            prune_hides_after_days: 0,
+            max_history_keys: 1_000_000,
+            ignore_unknown_transactions: false,
+            max_auto_show_per_run: 0,
+            buffer_writes: true,
+            max_line_bytes: 65_536,
+            launch_retries: 2,
+            compact_history_over_bytes: 1_048_576,
+            log_level: Level::Info,
+            log_format: Format::Text,
+            decay_days: 0,
+            rerun_after_exit: false,
+            lock_timeout_secs: 5,
+            auto_pin_after_shows: 0,
+            max_log_files: logging::DEFAULT_MAX_LOG_FILES,
+            max_log_file_size_bytes: logging::DEFAULT_MAX_FILE_SIZE,
+            unstick_after_hides: 0,
+            launch_command: None,
+            notify: false,
+            stream_history_over_bytes: 0,
+            log_timestamp: Timestamp::Iso,
+            auto_hide_cooldown_secs: 0,
+            user_thresholds: HashMap::new(),
+            pinned_users: HashSet::new(),
+            exempt_prefixes: Vec::new(),
+            lines: Vec::new(),
         }
     }
 }
 
+/// true if `HOOLIGAN_NO_WRITE_CONFIG` is set, so a missing config file should not be created
+pub fn write_default_suppressed() -> bool {
+    env::var_os(NO_WRITE_CONFIG_VAR).is_some_and(|value| !value.is_empty())
+}
+
 impl Config {
+    /// The defaults, for when there is no config file at `path`. Unless `write` is false they are also saved there so
+    /// that there's a file to edit, creating its directory if need be.
+    pub fn create_default(path: &Path, write: bool) -> Result<Self, io::Error> {
+        let config = Self::default();
+        if write {
+            if let Some(config_dir) = path.parent() {
+                fs::create_dir_all(config_dir)?;
+            }
+            config.serialize(path)?;
+        }
+        Ok(config)
+    }
+
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
         let file = File::open(path).map_err(Error::Io)?;
-        let reader = BufReader::new(file);
+        Self::read(BufReader::new(file))
+    }
+
+    fn read<R: BufRead>(reader: R) -> Result<Self, Error> {
         let mut config = Self::new();
-        for line in reader.lines() {
+        for (index, line) in reader.lines().enumerate() {
             let line = line.map_err(Error::Io)?;
-            config.parse_line(&line)?;
+            config.parse_line(line).map_err(|e| Error::Line(index + 1, Box::new(e)))?;
         }
         Ok(config)
     }
 
-    const fn new() -> Self {
+    /// the starting point for reading a config file: the defaults, except that a file without a version line predates
+    /// versioning and a file without `auto_hide_threshold` has a threshold of 0
+    fn new() -> Self {
         Self {
+            version: 0,
             auto_hide_threshold: 0,
+            ..Self::default()
         }
     }
 
-    fn parse_line(&mut self, line: &str) -> Result<(), Error> {
+    fn parse_line(&mut self, line: String) -> Result<(), Error> {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            self.lines.push(Line::Verbatim(line));
+            return Ok(());
+        }
+
         let (key, value) = line.split_once('=').ok_or(Error::Split)?;
+        if key == CONFIG_VERSION {
+            self.version = parse_int(value)?;
+            self.lines.push(Line::Version);
+            return Ok(());
+        }
+        if let Some(user) = key.strip_prefix(USER_THRESHOLD_PREFIX) {
+            self.set_user_threshold(user, value)?;
+            self.lines.push(Line::UserThreshold(user.to_owned()));
+            return Ok(());
+        }
+        if key == PIN {
+            self.pin(value)?;
+            self.lines.push(Line::Pin(value.to_owned()));
+            return Ok(());
+        }
+        if key == EXEMPT_PREFIX {
+            self.exempt_prefix(value)?;
+            self.lines.push(Line::ExemptPrefix(value.to_owned()));
+            return Ok(());
+        }
+        match Key::parse(key) {
+            Some(key) => {
+                self.set(key, value)?;
+                self.lines.push(Line::Key(key));
+            }
+            None => self.lines.push(Line::Verbatim(line)), // keep keys we don't know about for forward compatibility
+        }
+        Ok(())
+    }
+
+    /// Upgrade a config read from an older version to the current one. Returns true if anything changed, in which case
+    /// the config should be saved. Configs from a newer version are left alone; keys this build doesn't know are kept
+    /// as-is either way.
+    pub fn migrate(&mut self) -> bool {
+        if self.version >= CURRENT_VERSION {
+            return false;
+        }
+        // 0 to 1: only the version line is new. Missing keys are written with their defaults on serialize.
+        self.version = CURRENT_VERSION;
+        true
+    }
+
+    /// Apply a `key=value` override for this run only. Unlike the config file, unknown keys are an error, and the
+    /// override is not remembered if the config is serialized.
+    pub fn apply_override(&mut self, line: &str) -> Result<(), Error> {
+        let (key, value) = line.split_once('=').ok_or(Error::Split)?;
+        if let Some(user) = key.strip_prefix(USER_THRESHOLD_PREFIX) {
+            return self.set_user_threshold(user, value);
+        }
+        if key == PIN {
+            return self.pin(value);
+        }
+        if key == EXEMPT_PREFIX {
+            return self.exempt_prefix(value);
+        }
+        let key = Key::parse(key).ok_or(Error::Key)?;
+        self.set(key, value)
+    }
+
+    /// The number of shows it takes for `user` to stick: their override if they have one, or `auto_hide_threshold`
+    pub fn threshold_for(&self, user: &str) -> u32 {
+        self.user_thresholds.get(user).copied().unwrap_or(self.auto_hide_threshold)
+    }
+
+    /// false if `user`'s threshold is 0, which means they are never auto hidden
+    pub fn auto_hides(&self, user: &str) -> bool {
+        self.threshold_for(user) != 0
+    }
+
+    fn set_user_threshold(&mut self, user: &str, value: &str) -> Result<(), Error> {
+        validate_user(user)?;
+        self.user_thresholds.insert(user.to_owned(), parse_int(value)?);
+        Ok(())
+    }
+
+    pub fn is_pinned(&self, user: &str) -> bool {
+        self.pinned_users.contains(user)
+    }
+
+    fn pin(&mut self, user: &str) -> Result<(), Error> {
+        validate_user(user)?;
+        self.pinned_users.insert(user.to_owned());
+        Ok(())
+    }
+
+    /// true if `user` starts with any `exempt_prefix`
+    pub fn is_exempt(&self, user: &str) -> bool {
+        self.exempt_prefixes.iter().any(|prefix| user.as_bytes().starts_with(prefix))
+    }
+
+    fn has_exempt_prefix(&self, prefix: &str) -> bool {
+        self.exempt_prefixes.iter().any(|existing| **existing == *prefix.as_bytes())
+    }
+
+    fn exempt_prefix(&mut self, prefix: &str) -> Result<(), Error> {
+        // an empty prefix would exempt everyone, and a prefix with whitespace can't match any key
+        validate_user(prefix)?;
+        if !self.has_exempt_prefix(prefix) {
+            self.exempt_prefixes.push(prefix.as_bytes().into());
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: Key, value: &str) -> Result<(), Error> {
         match key {
-            AUTO_HIDE_THRESHOLD => self.parse_auto_hide_threshold(value),
-            _ => Err(Error::Key),
+            Key::AutoHideThreshold => self.parse_auto_hide_threshold(value)?,
+            Key::TrackStats => self.track_stats = parse_bool(value)?,
+            Key::WarmupRuns => self.warmup_runs = parse_int(value)?,
+            Key::IncludeGlob => self.include_glob = parse_optional_string(value),
+            Key::ExcludeGlob => self.exclude_glob = parse_optional_string(value),
+            Key::PruneHidesAfterDays => self.prune_hides_after_days = parse_int(value)?,
+            Key::MaxHistoryKeys => self.max_history_keys = parse_int(value)?,
+            Key::IgnoreUnknownTransactions => self.ignore_unknown_transactions = parse_bool(value)?,
+            Key::MaxAutoShowPerRun => self.max_auto_show_per_run = parse_int(value)?,
+            Key::BufferWrites => self.buffer_writes = parse_bool(value)?,
+            Key::MaxLineBytes => self.max_line_bytes = parse_int(value)?,
+            Key::LaunchRetries => self.launch_retries = parse_int(value)?,
+            Key::CompactHistoryOverBytes => self.compact_history_over_bytes = parse_int(value)?,
+            Key::LogLevel => self.log_level = Level::parse(value).ok_or(Error::LogLevel)?,
+            Key::LogFormat => self.log_format = Format::parse(value).ok_or(Error::LogFormat)?,
+            Key::DecayDays => self.decay_days = parse_int(value)?,
+            Key::RerunAfterExit => self.rerun_after_exit = parse_bool(value)?,
+            Key::LockTimeoutSecs => self.lock_timeout_secs = parse_int(value)?,
+            Key::AutoPinAfterShows => self.auto_pin_after_shows = parse_int(value)?,
+            Key::MaxLogFiles => self.max_log_files = parse_int(value)?,
+            Key::MaxLogFileSizeBytes => self.max_log_file_size_bytes = parse_int(value)?,
+            Key::UnstickAfterHides => self.unstick_after_hides = parse_int(value)?,
+            Key::LaunchCommand => self.launch_command = parse_optional_string(value),
+            Key::Notify => self.notify = parse_bool(value)?,
+            Key::StreamHistoryOverBytes => self.stream_history_over_bytes = parse_int(value)?,
+            Key::LogTimestamp => self.log_timestamp = Timestamp::parse(value).ok_or(Error::LogTimestamp)?,
+            Key::AutoHideCooldownSecs => self.auto_hide_cooldown_secs = parse_int(value)?,
         }
+        Ok(())
     }
 
     fn parse_auto_hide_threshold(&mut self, value: &str) -> Result<(), Error> {
@@ -54,11 +470,159 @@ impl Config {
     }
 
     pub fn serialize<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
-        let file = File::create_new(path).unwrap();
+        let file = File::create(path)?;
         let mut writer = BufWriter::new(file);
-        writeln!(writer, "{}={}", AUTO_HIDE_THRESHOLD, self.auto_hide_threshold)?;
+        self.write(&mut writer)?;
         writer.flush()
     }
+
+    /// Write the original lines back out, updating values of known keys and appending any known keys that were absent
+    fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        for line in &self.lines {
+            match line {
+                Line::Version => writeln!(writer, "{CONFIG_VERSION}={}", self.version)?,
+                Line::Key(key) => self.write_key(writer, *key)?,
+                Line::UserThreshold(user) => self.write_user_threshold(writer, user)?,
+                Line::Pin(user) => {
+                    if self.is_pinned(user) {
+                        writeln!(writer, "{PIN}={user}")?;
+                    }
+                }
+                Line::ExemptPrefix(prefix) => {
+                    if self.has_exempt_prefix(prefix) {
+                        writeln!(writer, "{EXEMPT_PREFIX}={prefix}")?;
+                    }
+                }
+                Line::Verbatim(line) => writeln!(writer, "{line}")?,
+            }
+        }
+        if !self.lines.iter().any(|line| matches!(line, Line::Version)) {
+            writeln!(writer, "{CONFIG_VERSION}={}", self.version)?;
+        }
+        for key in Key::ALL {
+            if !self.lines.iter().any(|line| matches!(line, Line::Key(existing) if *existing == key)) {
+                self.write_key(writer, key)?;
+            }
+        }
+        let mut new_users: Vec<&String> = self.user_thresholds.keys()
+            .filter(|user| !self.lines.iter().any(|line| matches!(line, Line::UserThreshold(existing) if existing == *user)))
+            .collect();
+        new_users.sort_unstable();
+        for user in new_users {
+            self.write_user_threshold(writer, user)?;
+        }
+        let mut new_pins: Vec<&String> = self.pinned_users.iter()
+            .filter(|user| !self.lines.iter().any(|line| matches!(line, Line::Pin(existing) if existing == *user)))
+            .collect();
+        new_pins.sort_unstable();
+        for user in new_pins {
+            writeln!(writer, "{PIN}={user}")?;
+        }
+        let new_prefixes = self.exempt_prefixes.iter()
+            .map(|prefix| String::from_utf8_lossy(prefix))
+            .filter(|prefix| !self.lines.iter().any(|line| matches!(line, Line::ExemptPrefix(existing) if existing == prefix)));
+        for prefix in new_prefixes {
+            writeln!(writer, "{EXEMPT_PREFIX}={prefix}")?;
+        }
+        Ok(())
+    }
+
+    /// every `key` a config file may set with `key=value`, not counting per-user ones
+    pub fn key_names() -> impl Iterator<Item=&'static str> {
+        Key::ALL.into_iter().map(Key::serialize)
+    }
+
+    /// Check a moderations filename against `include_glob` and `exclude_glob`
+    pub fn includes_file(&self, filename: &str) -> bool {
+        self.include_glob.as_deref().is_none_or(|glob| glob::matches(glob, filename))
+            && !self.exclude_glob.as_deref().is_some_and(|glob| glob::matches(glob, filename))
+    }
+
+    /// Write a user's threshold override, or nothing if it has since been removed
+    fn write_user_threshold<W: Write>(&self, writer: &mut W, user: &str) -> Result<(), io::Error> {
+        match self.user_thresholds.get(user) {
+            Some(threshold) => writeln!(writer, "{USER_THRESHOLD_PREFIX}{user}={threshold}"),
+            None => Ok(()),
+        }
+    }
+
+    fn write_key<W: Write>(&self, writer: &mut W, key: Key) -> Result<(), io::Error> {
+        match key {
+            Key::AutoHideThreshold => writeln!(writer, "{}={}", key.serialize(), self.auto_hide_threshold),
+            Key::TrackStats => writeln!(writer, "{}={}", key.serialize(), self.track_stats),
+            Key::WarmupRuns => writeln!(writer, "{}={}", key.serialize(), self.warmup_runs),
+            Key::IncludeGlob => writeln!(writer, "{}={}", key.serialize(), self.include_glob.as_deref().unwrap_or_default()),
+            Key::ExcludeGlob => writeln!(writer, "{}={}", key.serialize(), self.exclude_glob.as_deref().unwrap_or_default()),
+            Key::PruneHidesAfterDays => writeln!(writer, "{}={}", key.serialize(), self.prune_hides_after_days),
+            Key::MaxHistoryKeys => writeln!(writer, "{}={}", key.serialize(), self.max_history_keys),
+            Key::IgnoreUnknownTransactions => writeln!(writer, "{}={}", key.serialize(), self.ignore_unknown_transactions),
+            Key::MaxAutoShowPerRun => writeln!(writer, "{}={}", key.serialize(), self.max_auto_show_per_run),
+            Key::BufferWrites => writeln!(writer, "{}={}", key.serialize(), self.buffer_writes),
+            Key::MaxLineBytes => writeln!(writer, "{}={}", key.serialize(), self.max_line_bytes),
+            Key::LaunchRetries => writeln!(writer, "{}={}", key.serialize(), self.launch_retries),
+            Key::CompactHistoryOverBytes => writeln!(writer, "{}={}", key.serialize(), self.compact_history_over_bytes),
+            Key::LogLevel => writeln!(writer, "{}={}", key.serialize(), self.log_level.serialize()),
+            Key::LogFormat => writeln!(writer, "{}={}", key.serialize(), self.log_format.serialize()),
+            Key::DecayDays => writeln!(writer, "{}={}", key.serialize(), self.decay_days),
+            Key::RerunAfterExit => writeln!(writer, "{}={}", key.serialize(), self.rerun_after_exit),
+            Key::LockTimeoutSecs => writeln!(writer, "{}={}", key.serialize(), self.lock_timeout_secs),
+            Key::AutoPinAfterShows => writeln!(writer, "{}={}", key.serialize(), self.auto_pin_after_shows),
+            Key::MaxLogFiles => writeln!(writer, "{}={}", key.serialize(), self.max_log_files),
+            Key::MaxLogFileSizeBytes => writeln!(writer, "{}={}", key.serialize(), self.max_log_file_size_bytes),
+            Key::UnstickAfterHides => writeln!(writer, "{}={}", key.serialize(), self.unstick_after_hides),
+            Key::LaunchCommand => writeln!(writer, "{}={}", key.serialize(), self.launch_command.as_deref().unwrap_or_default()),
+            Key::Notify => writeln!(writer, "{}={}", key.serialize(), self.notify),
+            Key::StreamHistoryOverBytes => writeln!(writer, "{}={}", key.serialize(), self.stream_history_over_bytes),
+            Key::LogTimestamp => writeln!(writer, "{}={}", key.serialize(), self.log_timestamp.serialize()),
+            Key::AutoHideCooldownSecs => writeln!(writer, "{}={}", key.serialize(), self.auto_hide_cooldown_secs),
+        }
+    }
+}
+
+/// user keys can't be empty or contain whitespace, as .vrcset lines are whitespace-separated
+fn validate_user(user: &str) -> Result<(), Error> {
+    if user.is_empty() || user.contains(char::is_whitespace) {
+        Err(Error::Key)
+    } else {
+        Ok(())
+    }
+}
+
+/// Split a `launch_command` value into a program and its args on whitespace. A double-quoted section is kept together
+/// even if it contains whitespace, and the quotes themselves are dropped.
+pub fn split_command(value: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    for c in value.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+/// an empty value means the setting is unset
+fn parse_optional_string(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_owned())
+}
+
+fn parse_int<T: FromStr>(value: &str) -> Result<T, Error> {
+    value.parse().map_err(|_| Error::Int)
+}
+
+fn parse_bool(value: &str) -> Result<bool, Error> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(Error::Bool),
+    }
 }
 
 #[allow(dead_code)] // lint misses usage in debug printing this error
@@ -66,6 +630,188 @@ impl Config {
 pub enum Error {
     Split,
     Int,
+    Bool,
     Key,
+    LogLevel,
+    LogFormat,
+    LogTimestamp,
+    /// the 1-based line number of the config file that failed to parse, and why
+    Line(usize, Box<Error>),
     Io(io::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    fn serialize(config: &Config) -> String {
+        let mut buffer = Vec::new();
+        config.write(&mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn test_config_migrate_version_0() {
+        let input = "# my settings\nauto_hide_threshold=5\nsome_future_key=hello\n";
+        let mut config = Config::read(input.as_bytes()).unwrap();
+        assert_eq!(config.version, 0);
+        assert!(config.migrate());
+        assert_eq!(config.version, CURRENT_VERSION);
+        assert_eq!(config.auto_hide_threshold, 5);
+        let serialized = serialize(&config);
+        assert!(serialized.starts_with(&format!("{input}config_version={CURRENT_VERSION}\ntrack_stats=false\n")));
+
+        // the migrated file loads as the current version, with nothing left to do
+        let mut config = Config::read(serialized.as_bytes()).unwrap();
+        assert_eq!(config.version, CURRENT_VERSION);
+        assert!(!config.migrate());
+        assert_eq!(serialize(&config), serialized);
+    }
+
+    #[test]
+    fn test_config_newer_version() {
+        let input = "config_version=99\nauto_hide_threshold=5\nsome_future_key=hello\n";
+        let mut config = Config::read(input.as_bytes()).unwrap();
+        assert_eq!(config.version, 99);
+        assert!(!config.migrate());
+        assert!(serialize(&config).starts_with(input));
+        assert!(matches!(config.apply_override("config_version=2"), Err(Error::Key)));
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let input = "config_version=1\n# my settings\n\nauto_hide_threshold=5\nsome_future_key=hello\ntrack_stats=true\nwarmup_runs=2\ninclude_glob=*.vrcset\nexclude_glob=\nprune_hides_after_days=30\nmax_history_keys=1000\nignore_unknown_transactions=true\nmax_auto_show_per_run=10\nbuffer_writes=false\nmax_line_bytes=128\nlaunch_retries=5\ncompact_history_over_bytes=4096\nlog_level=debug\nlog_format=json\ndecay_days=90\nrerun_after_exit=true\nlock_timeout_secs=30\nauto_pin_after_shows=6\nmax_log_files=3\nmax_log_file_size_bytes=1048576\nunstick_after_hides=3\nlaunch_command=steam.exe -applaunch 438100\nnotify=true\nstream_history_over_bytes=67108864\nlog_timestamp=epoch\nauto_hide_cooldown_secs=3600\n";
+        let config = Config::read(input.as_bytes()).unwrap();
+        assert_eq!(config.auto_hide_threshold, 5);
+        assert!(config.track_stats);
+        assert_eq!(config.warmup_runs, 2);
+        assert_eq!(config.include_glob.as_deref(), Some("*.vrcset"));
+        assert_eq!(config.exclude_glob, None);
+        assert_eq!(serialize(&config), input);
+    }
+
+    #[test]
+    fn test_config_round_trip_updates_known_keys() {
+        let input = "# my settings\nauto_hide_threshold=5\nsome_future_key=hello\n";
+        let mut config = Config::read(input.as_bytes()).unwrap();
+        config.auto_hide_threshold = 2;
+        assert!(serialize(&config).starts_with("# my settings\nauto_hide_threshold=2\nsome_future_key=hello\n"));
+    }
+
+    #[test]
+    fn test_config_includes_file() {
+        let config = Config::read("exclude_glob=test_*\n".as_bytes()).unwrap();
+        assert!(config.includes_file("LocalPlayerModerations.vrcset"));
+        assert!(!config.includes_file("test_LocalPlayerModerations.vrcset"));
+    }
+
+    #[test]
+    fn test_config_override() {
+        let mut config = Config::read("auto_hide_threshold=3\n".as_bytes()).unwrap();
+        config.apply_override("auto_hide_threshold=5").unwrap();
+        assert_eq!(config.auto_hide_threshold, 5);
+        assert!(serialize(&config).starts_with("auto_hide_threshold=5\n"));
+        assert!(matches!(config.apply_override("not_a_key=5"), Err(Error::Key)));
+    }
+
+    #[test]
+    fn test_config_user_thresholds() {
+        let input = "auto_hide_threshold=3\nthreshold.usr_a=10\n# comment\nthreshold.usr_b=0\n";
+        let config = Config::read(input.as_bytes()).unwrap();
+        assert_eq!(config.threshold_for("usr_a"), 10);
+        assert_eq!(config.threshold_for("usr_b"), 0);
+        assert_eq!(config.threshold_for("usr_c"), 3);
+        assert!(serialize(&config).starts_with(input));
+    }
+
+    #[test]
+    fn test_config_user_thresholds_updated() {
+        let mut config = Config::read("threshold.usr_a=10\nthreshold.usr_b=5\n".as_bytes()).unwrap();
+        config.user_thresholds.remove("usr_a");
+        config.apply_override("threshold.usr_c=7").unwrap();
+        let serialized = serialize(&config);
+        assert!(serialized.starts_with("threshold.usr_b=5\n"));
+        assert!(serialized.ends_with("threshold.usr_c=7\n"));
+        assert!(!serialized.contains("usr_a"));
+    }
+
+    #[test]
+    fn test_config_user_threshold_invalid() {
+        assert!(matches!(Config::read("threshold.=10\n".as_bytes()), Err(Error::Line(1, e)) if matches!(*e, Error::Key)));
+        assert!(matches!(Config::read("threshold.usr_a=lots\n".as_bytes()), Err(Error::Line(1, e)) if matches!(*e, Error::Int)));
+    }
+
+    #[test]
+    fn test_config_pins() {
+        let input = "pin=usr_a\n# friends\npin=usr_b\n";
+        let mut config = Config::read(input.as_bytes()).unwrap();
+        assert!(config.is_pinned("usr_a"));
+        assert!(config.is_pinned("usr_b"));
+        assert!(!config.is_pinned("usr_c"));
+        assert!(serialize(&config).starts_with(input));
+
+        config.pinned_users.remove("usr_a");
+        config.apply_override("pin=usr_c").unwrap();
+        let serialized = serialize(&config);
+        assert!(serialized.starts_with("# friends\npin=usr_b\n"));
+        assert!(serialized.ends_with("pin=usr_c\n"));
+        assert!(matches!(config.apply_override("pin="), Err(Error::Key)));
+    }
+
+    #[test]
+    fn test_config_error_line() {
+        let result = Config::read("# my settings\nauto_hide_threshold=5\ntrack_stats=yes\n".as_bytes());
+        match result {
+            Err(Error::Line(3, e)) => assert!(matches!(*e, Error::Bool)),
+            _ => panic!("expected a bool error on line 3"),
+        }
+    }
+
+    #[test]
+    fn test_config_default_round_trip() {
+        let config = Config::read(serialize(&Config::default()).as_bytes()).unwrap();
+        assert_eq!(config.auto_hide_threshold, 3);
+        assert!(!config.track_stats);
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split_command("steam.exe -applaunch 438100"), ["steam.exe", "-applaunch", "438100"]);
+        assert_eq!(split_command("  steam.exe\t-applaunch  438100 "), ["steam.exe", "-applaunch", "438100"]);
+        assert_eq!(split_command(r#""C:\Program Files (x86)\Steam\steam.exe" -applaunch 438100"#), [r"C:\Program Files (x86)\Steam\steam.exe", "-applaunch", "438100"]);
+        assert_eq!(split_command(r#"start.exe "" --flag=a" "b"#), ["start.exe", "", "--flag=a b"]);
+        assert!(split_command("").is_empty());
+        assert!(split_command("   ").is_empty());
+    }
+
+    #[test]
+    fn test_create_default() {
+        let dir = TestDir::new("create-default");
+        let path = dir.join("config").join("config.props");
+
+        let config = Config::create_default(path.as_path(), false).unwrap();
+        assert_eq!(config.auto_hide_threshold, Config::default().auto_hide_threshold);
+        assert_eq!(fs::read_dir(dir.as_path()).unwrap().count(), 0);
+
+        Config::create_default(path.as_path(), true).unwrap();
+        assert_eq!(Config::load(path.as_path()).unwrap().auto_hide_threshold, config.auto_hide_threshold);
+    }
+
+    #[test]
+    fn test_exempt_prefix() {
+        let mut config = Config::read("exempt_prefix=usr_test\nexempt_prefix=usr_alt\n".as_bytes()).unwrap();
+        assert!(config.is_exempt("usr_test-6b683acd"));
+        assert!(config.is_exempt("usr_alt"));
+        assert!(!config.is_exempt("usr_6b683acd-31a6-495d-aa46-a73c1349f462"));
+        assert!(!config.is_exempt("usr_tes"));
+        assert!(matches!(config.apply_override("exempt_prefix="), Err(Error::Key)));
+
+        config.exempt_prefixes.retain(|prefix| **prefix != *b"usr_alt");
+        config.apply_override("exempt_prefix=usr_bot").unwrap();
+        let serialized = serialize(&config);
+        assert!(serialized.contains("exempt_prefix=usr_test\n"));
+        assert!(serialized.contains("exempt_prefix=usr_bot\n"));
+        assert!(!serialized.contains("usr_alt"));
+    }
+}
@@ -0,0 +1,37 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Just enough CSV serialization for exporting history to a spreadsheet
+
+/// Serialize `value` as a CSV field, quoting it only if it contains something that would otherwise end the field
+pub fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Serialize `fields` as one CSV record, including the line ending
+pub fn record<I: IntoIterator<Item=S>, S: AsRef<str>>(fields: I) -> String {
+    let fields: Vec<String> = fields.into_iter().map(|value| field(value.as_ref())).collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(field("usr_1234"), "usr_1234");
+        assert_eq!(field("a, \"quoted\"\nvalue"), "\"a, \"\"quoted\"\"\nvalue\"");
+        assert_eq!(field(""), "");
+    }
+
+    #[test]
+    fn test_csv_record() {
+        assert_eq!(record(["usr_1234", "3", "a,b"]), "usr_1234,3,\"a,b\"\r\n");
+    }
+}
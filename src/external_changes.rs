@@ -0,0 +1,99 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Compare a .vrcset file against the state hooligan last recorded in its history, to find changes made by VRChat or
+//! by hand between runs
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::json;
+use crate::local_player_moderations as moderation;
+use crate::transaction::ShowHideCount;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ExternalChanges {
+    /// users shown in the file that history did not have as shown
+    pub shown: BTreeSet<String>,
+    /// users hidden in the file that history did not have as hidden
+    pub hidden: BTreeSet<String>,
+    /// users absent from the file that history had as shown or hidden
+    pub cleared: BTreeSet<String>,
+}
+
+impl ExternalChanges {
+    pub fn compute<I: IntoIterator<Item=moderation::Line>>(lines: I, mut history: HashMap<String, ShowHideCount>) -> Self {
+        let mut changes = Self::default();
+        for line in lines {
+            let state = history.remove(&line.key);
+            match line.value {
+                moderation::Value::Show => {
+                    if !state.is_some_and(|state| state.is_shown()) {
+                        changes.shown.insert(line.key);
+                    }
+                }
+                moderation::Value::Hide => {
+                    if !state.is_some_and(|state| state.is_hidden()) {
+                        changes.hidden.insert(line.key);
+                    }
+                }
+                moderation::Value::Other(_) => {}
+            }
+        }
+        changes.cleared = history.into_iter()
+            .filter(|(_, state)| !state.is_default())
+            .map(|(key, _)| key)
+            .collect();
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shown.is_empty() && self.hidden.is_empty() && self.cleared.is_empty()
+    }
+
+    /// one `<change> <key>` line per changed user
+    pub fn to_human(&self) -> String {
+        if self.is_empty() {
+            return "no external changes\n".to_owned();
+        }
+        let mut human = String::new();
+        for (change, keys) in [("shown", &self.shown), ("hidden", &self.hidden), ("cleared", &self.cleared)] {
+            for key in keys {
+                human.push_str(&format!("{change} {key}\n"));
+            }
+        }
+        human
+    }
+
+    pub fn to_json(&self) -> String {
+        format!("{{\"shown\":{},\"hidden\":{},\"cleared\":{}}}\n",
+                json::string_array(&self.shown),
+                json::string_array(&self.hidden),
+                json::string_array(&self.cleared))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_util::TestFile;
+    use crate::transaction;
+
+    #[test]
+    fn test_external_changes() {
+        let file = TestFile::new("external-changes", "usr_shown MANUAL_SHOW\nusr_hidden MANUAL_HIDE\nusr_reset AUTO_RESET\nusr_gone MANUAL_SHOW\n");
+        let history = transaction::read_log(&file, &Config::default(), 0, |_| {}).unwrap();
+        let lines = [
+            moderation::Line::new("usr_shown".to_string(), moderation::Value::Show),
+            moderation::Line::new("usr_hidden".to_string(), moderation::Value::Show),
+            moderation::Line::new("usr_reset".to_string(), moderation::Value::Hide),
+            moderation::Line::new("usr_new".to_string(), moderation::Value::Show),
+        ];
+        let actual = ExternalChanges::compute(lines, history);
+        assert_eq!(actual.shown, BTreeSet::from(["usr_hidden".to_string(), "usr_new".to_string()]));
+        assert_eq!(actual.hidden, BTreeSet::from(["usr_reset".to_string()]));
+        assert_eq!(actual.cleared, BTreeSet::from(["usr_gone".to_string()]));
+        assert_eq!(actual.to_json(), "{\"shown\":[\"usr_hidden\",\"usr_new\"],\"hidden\":[\"usr_reset\"],\"cleared\":[\"usr_gone\"]}\n");
+    }
+}
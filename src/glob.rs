@@ -0,0 +1,66 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Minimal glob matching, supporting `*` (any run of characters) and `?` (any single character)
+
+/// Check if `text` matches the whole of `pattern`
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` in the pattern, and where in the text we were when we hit it
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+            }
+            Some(c) if *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // let the last `*` swallow one more character and try again
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_literal() {
+        assert!(matches("foo.vrcset", "foo.vrcset"));
+        assert!(!matches("foo.vrcset", "bar.vrcset"));
+    }
+
+    #[test]
+    fn test_glob_star() {
+        assert!(matches("test_*", "test_foo.vrcset"));
+        assert!(matches("*.vrcset", "foo.vrcset"));
+        assert!(matches("*usr_*.vrcset", "LocalPlayerModerations_usr_1234.vrcset"));
+        assert!(!matches("test_*", "foo_test_.vrcset"));
+        assert!(matches("*", ""));
+    }
+
+    #[test]
+    fn test_glob_question_mark() {
+        assert!(matches("?.vrcset", "a.vrcset"));
+        assert!(!matches("?.vrcset", "ab.vrcset"));
+    }
+}
@@ -0,0 +1,93 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Just enough JSON for the machine-readable output of read-only commands, and for reading back the JSON log lines
+//! hooligan writes itself
+
+use std::fmt::Write;
+
+/// Serialize `value` as a JSON string literal
+pub fn string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", u32::from(c));
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Parse the JSON string literal at the start of `json`, returning its value and whatever follows it
+pub fn parse_string(json: &str) -> Option<(String, &str)> {
+    let mut chars = json.strip_prefix('"')?.chars();
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some((value, chars.as_str())),
+            '\\' => match chars.next()? {
+                '"' => value.push('"'),
+                '\\' => value.push('\\'),
+                '/' => value.push('/'),
+                'b' => value.push('\u{8}'),
+                'f' => value.push('\u{c}'),
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                'u' => {
+                    let hex = chars.as_str().get(..4)?;
+                    value.push(char::from_u32(u32::from_str_radix(hex, 16).ok()?)?);
+                    chars.nth(3);
+                }
+                _ => return None,
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Serialize `values` as a JSON array of strings
+pub fn string_array<I: IntoIterator<Item=S>, S: AsRef<str>>(values: I) -> String {
+    let values: Vec<String> = values.into_iter().map(|value| string(value.as_ref())).collect();
+    format!("[{}]", values.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(string("usr_1234"), "\"usr_1234\"");
+        assert_eq!(string("a \"quoted\"\\path\n"), "\"a \\\"quoted\\\"\\\\path\\n\"");
+        assert_eq!(string("\u{1}"), "\"\\u0001\"");
+    }
+
+    #[test]
+    fn test_json_parse_string() {
+        for value in ["usr_1234", "a \"quoted\"\\path\n", "\u{1}\t", ""] {
+            assert_eq!(parse_string(&string(value)), Some((value.to_owned(), "")));
+        }
+        assert_eq!(parse_string("\"a\\/b\",\"c\""), Some(("a/b".to_owned(), ",\"c\"")));
+        assert_eq!(parse_string("\"unterminated"), None);
+        assert_eq!(parse_string("\"bad \\x escape\""), None);
+        assert_eq!(parse_string("not a string"), None);
+    }
+
+    #[test]
+    fn test_json_string_array() {
+        assert_eq!(string_array(["a", "b"]), "[\"a\",\"b\"]");
+        assert_eq!(string_array(Vec::<String>::new()), "[]");
+    }
+}
@@ -0,0 +1,95 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Everything hooligan knows about reading and writing LocalPlayerModerations (.vrcset) files, its per-file history,
+//! and its config. The hooligan binary is a thin wrapper around this.
+//!
+//! ```
+//! use std::fs::{self, File};
+//!
+//! use hooligan::config::Config;
+//! use hooligan::local_player_moderations::{Line, Value};
+//! use hooligan::{current_timestamp, transaction};
+//!
+//! let line = Line::parse("usr_6b683acd-31a6-495d-aa46-a73c1349f462 005").unwrap();
+//! assert_eq!(line.value, Value::Show);
+//!
+//! let history_path = std::env::temp_dir().join(format!("hooligan-doc-{}.history", std::process::id()));
+//! fs::write(history_path.as_path(), "usr_6b683acd-31a6-495d-aa46-a73c1349f462 MANUAL_SHOW\n").unwrap();
+//! let history = File::open(history_path.as_path()).unwrap();
+//! let shows = transaction::read_log(&history, &Config::default(), current_timestamp(), |skipped| eprintln!("skipped {skipped:?}")).unwrap();
+//! assert_eq!(shows[&line.key].count(), 1);
+//! # fs::remove_file(history_path).unwrap();
+//! ```
+
+use std::{env, io};
+use std::ffi::OsString;
+use std::num::TryFromIntError;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::local_player_moderations as moderation;
+
+pub mod atomic;
+pub mod cli;
+pub mod local_player_moderations;
+pub mod lock;
+pub mod logging;
+pub mod platform;
+pub mod stats;
+pub mod timeline;
+pub mod transaction;
+pub mod config;
+pub mod csv;
+pub mod external_changes;
+pub mod glob;
+pub mod json;
+pub mod report;
+pub mod run;
+#[cfg(test)]
+mod test_util;
+
+#[allow(dead_code)] // lint misses usage in debug printing this error
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    ShowHideParse(moderation::ParseError),
+    TransactionParse(transaction::ParseError),
+    EnvironmentVar(env::VarError),
+    U64FromInt(TryFromIntError),
+    BadFilename(OsString),
+    ConfigLoad(config::Error),
+    Args(cli::Error),
+    /// the moderations directory path, from `HOOLIGAN_MODERATIONS_DIR` or `--moderations-dir`, points at a file
+    ModerationsPathIsFile(PathBuf),
+    /// the moderations directory given with `--moderations-dir` does not exist
+    ModerationsPathNotFound(PathBuf),
+    /// a history file has more distinct users than `max_history_keys`
+    HistoryTooLarge,
+    /// a history file doesn't match the checkpoint on this line, so it was changed or damaged since it was written
+    HistoryChecksum(u64),
+    Lock(lock::Error),
+    /// VRChat is already running, so its .vrcset files were left alone
+    VrchatRunning,
+    /// `--confirm` was given where there's no console to ask in
+    ConfirmUnsupported,
+}
+
+/// FNV-1a hash of no bytes at all, to start a new hash from
+pub(crate) const FNV1A_EMPTY: u64 = 0xcbf29ce484222325;
+
+/// Continue an FNV-1a hash with more bytes. Used instead of `DefaultHasher` where a hash gets written to disk and so needs
+/// to be stable across builds.
+pub(crate) fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// current unix timestamp in seconds
+pub fn current_timestamp() -> u64 {
+    SystemTime::UNIX_EPOCH.elapsed().map_or(0, |duration| duration.as_secs())
+}
@@ -4,6 +4,9 @@
 
 //! Serialization and deserialization logic for the LocalPlayerModerations file format
 
+use std::io::{self, BufRead};
+use std::ops::Range;
+
 const HIDE_AVATAR_VALUE: &str = "004";
 const SHOW_AVATAR_VALUE: &str = "005";
 
@@ -42,8 +45,38 @@ impl Line {
         })
     }
 
+    /// Like [`Self::parse`], but returns `None` for a blank line. VRChat sometimes leaves these at the end of the file.
+    pub fn parse_non_blank(line: &str) -> Option<Result<Self, ParseError>> {
+        if line.trim().is_empty() {
+            None
+        } else {
+            Some(Self::parse(line))
+        }
+    }
+
     pub fn serialize(&self) -> String {
-        format!("{:63} {}\r\n", self.key, self.value.serialize())
+        self.serialize_with(LineEnding::CrLf)
+    }
+
+    pub fn serialize_with(&self, line_ending: LineEnding) -> String {
+        format!("{:63} {}{}", self.key, self.value.serialize(), line_ending.as_str())
+    }
+}
+
+/// How lines in a file are terminated. VRChat writes CRLF.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    CrLf,
+    Lf,
+}
+
+impl LineEnding {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::CrLf => "\r\n",
+            Self::Lf => "\n",
+        }
     }
 }
 
@@ -51,6 +84,8 @@ impl Line {
 pub enum Value {
     Hide,
     Show,
+    /// any other moderation VRChat stores in the same file, such as mutes and blocks. Hooligan leaves these alone.
+    Other(u16),
 }
 
 impl Value {
@@ -59,14 +94,18 @@ impl Value {
         match value {
             HIDE_AVATAR_VALUE => Ok(Self::Hide),
             SHOW_AVATAR_VALUE => Ok(Self::Show),
+            other if other.len() == 3 && other.bytes().all(|byte| byte.is_ascii_digit()) => {
+                other.parse().map(Self::Other).map_err(|_| ParseError::UnknownValue(other.to_owned()))
+            }
             unknown_value => Err(ParseError::UnknownValue(unknown_value.to_owned())),
         }
     }
 
-    const fn serialize(&self) -> &str {
+    fn serialize(&self) -> String {
         match self {
-            Self::Hide => HIDE_AVATAR_VALUE,
-            Self::Show => SHOW_AVATAR_VALUE,
+            Self::Hide => HIDE_AVATAR_VALUE.to_owned(),
+            Self::Show => SHOW_AVATAR_VALUE.to_owned(),
+            Self::Other(value) => format!("{value:03}"),
         }
     }
 }
@@ -75,6 +114,114 @@ impl Value {
 pub enum ParseError {
     BadSplit(String),
     UnknownValue(String),
+    /// a line was longer than the limit. Contains the line's length in bytes, including its line ending.
+    TooLong(usize),
+}
+
+/// Like [`BufRead::lines`], but a line longer than `max_bytes` is yielded as [`ParseError::TooLong`] without ever
+/// holding much more than `max_bytes` of it in memory. A `max_bytes` of 0 means no limit.
+pub fn bounded_lines<R: BufRead>(reader: R, max_bytes: usize) -> BoundedLines<R> {
+    BoundedLines {
+        reader,
+        max_bytes,
+        crlf_lines: 0,
+        lf_lines: 0,
+        offset: 0,
+        too_long: Vec::new(),
+    }
+}
+
+pub struct BoundedLines<R> {
+    reader: R,
+    max_bytes: usize,
+    /// lines read so far ending in CRLF
+    crlf_lines: usize,
+    /// lines read so far ending in a bare LF
+    lf_lines: usize,
+    /// bytes read so far
+    offset: u64,
+    /// where each line yielded as [`ParseError::TooLong`] was
+    too_long: Vec<Range<u64>>,
+}
+
+impl<R: BufRead> BoundedLines<R> {
+    /// The line ending used by most of the lines read so far, or CRLF if there's no telling
+    pub const fn line_ending(&self) -> LineEnding {
+        if self.lf_lines > self.crlf_lines {
+            LineEnding::Lf
+        } else {
+            LineEnding::CrLf
+        }
+    }
+
+    /// The byte range of each line yielded as [`ParseError::TooLong`] so far, line endings included, so that the lines
+    /// can be copied somewhere safe before the file is rewritten without them
+    pub fn too_long(&self) -> &[Range<u64>] {
+        &self.too_long
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<Result<String, ParseError>>> {
+        // room for the limit plus a line ending; anything past that is counted but not kept
+        let capacity = if self.max_bytes == 0 { usize::MAX } else { self.max_bytes.saturating_add(2) };
+        let mut line: Vec<u8> = Vec::new();
+        let mut length: usize = 0;
+        let start = self.offset;
+        loop {
+            let buffer = match self.reader.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if buffer.is_empty() {
+                break;
+            }
+            let (chunk, done) = match buffer.iter().position(|&byte| byte == b'\n') {
+                Some(newline) => (&buffer[..=newline], true),
+                None => (buffer, false),
+            };
+            let kept = chunk.len().min(capacity - line.len());
+            line.extend_from_slice(&chunk[..kept]);
+            let consumed = chunk.len();
+            length += consumed;
+            self.offset += u64::try_from(consumed).map_err(io::Error::other)?;
+            self.reader.consume(consumed);
+            if done {
+                break;
+            }
+        }
+
+        if length == 0 {
+            return Ok(None);
+        }
+        if length > line.len() {
+            self.too_long.push(start..self.offset);
+            return Ok(Some(Err(ParseError::TooLong(length))));
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+                self.crlf_lines += 1;
+            } else {
+                self.lf_lines += 1;
+            }
+        }
+        if self.max_bytes != 0 && line.len() > self.max_bytes {
+            self.too_long.push(start..self.offset);
+            return Ok(Some(Err(ParseError::TooLong(length))));
+        }
+        String::from_utf8(line)
+            .map(|line| Some(Ok(line)))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<R: BufRead> Iterator for BoundedLines<R> {
+    type Item = io::Result<Result<String, ParseError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_line().transpose()
+    }
 }
 
 #[cfg(test)]
@@ -111,10 +258,43 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_line_other_value() {
+        for code in ["000", "001", "006", "009", "999"] {
+            let input = format!("{:63} {code}\r\n", "2ZaOGztkpc");
+            let actual = Line::parse(input.trim_end()).unwrap();
+            assert_eq!(actual.value, Value::Other(code.parse().unwrap()));
+            assert_eq!(actual.serialize(), input);
+        }
+    }
+
+    #[test]
+    fn test_line_show_hide_round_trip() {
+        for input in ["usr_6b683acd-31a6-495d-aa46-a73c1349f462                        004\r\n", "usr_6b683acd-31a6-495d-aa46-a73c1349f462                        005\r\n"] {
+            assert_eq!(Line::parse(input.trim_end()).unwrap().serialize(), input);
+        }
+    }
+
     #[test]
     fn test_line_unknown_value() {
-        let actual = Line::parse("2ZaOGztkpc                                                      009").unwrap_err();
-        let expected = ParseError::UnknownValue("009".to_string());
+        for value in ["09", "0009", "abc", "-01"] {
+            let actual = Line::parse(&format!("2ZaOGztkpc {value}")).unwrap_err();
+            let expected = ParseError::UnknownValue(value.to_string());
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_line_trailing_blank_lines() {
+        let file = "usr_6b683acd-31a6-495d-aa46-a73c1349f462                        004\r\n\r\n   \r\n";
+        let actual: Vec<Line> = file.lines()
+            .filter_map(Line::parse_non_blank)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected = vec![Line {
+            key: "usr_6b683acd-31a6-495d-aa46-a73c1349f462".to_string(),
+            value: Value::Hide,
+        }];
         assert_eq!(actual, expected);
     }
 
@@ -125,6 +305,52 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_bounded_lines() {
+        let file = "usr_a 004\r\nusr_b 005\nusr_c 005";
+        let actual: Vec<_> = bounded_lines(file.as_bytes(), 16).map(Result::unwrap).collect();
+        let expected = vec![Ok("usr_a 004".to_string()), Ok("usr_b 005".to_string()), Ok("usr_c 005".to_string())];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_bounded_lines_line_ending() {
+        for (file, expected) in [
+            ("usr_a 004\r\nusr_b 005\r\n", LineEnding::CrLf),
+            ("usr_a 004\nusr_b 005\n", LineEnding::Lf),
+            ("usr_a 004\nusr_b 005\r\nusr_c 005\n", LineEnding::Lf),
+            ("usr_a 004\nusr_b 005\r\n", LineEnding::CrLf),
+            ("usr_a 004", LineEnding::CrLf),
+            ("", LineEnding::CrLf),
+        ] {
+            let mut lines = bounded_lines(file.as_bytes(), 0);
+            lines.by_ref().for_each(drop);
+            assert_eq!(lines.line_ending(), expected, "{file:?}");
+        }
+        let line = Line::new("usr_a".to_owned(), Value::Hide);
+        assert!(line.serialize_with(LineEnding::Lf).ends_with(" 004\n"));
+        assert_eq!(line.serialize_with(LineEnding::CrLf), line.serialize());
+    }
+
+    #[test]
+    fn test_bounded_lines_too_long() {
+        let long_line = "x".repeat(10_000_000);
+        let file = format!("usr_a 004\r\n{long_line}\r\nusr_b 005\r\nusr_cc 0005\r\n");
+        let actual: Vec<_> = bounded_lines(file.as_bytes(), 10).map(Result::unwrap).collect();
+        let expected = vec![
+            Ok("usr_a 004".to_string()),
+            Err(ParseError::TooLong(10_000_002)),
+            Ok("usr_b 005".to_string()),
+            Err(ParseError::TooLong(13)),
+        ];
+        assert_eq!(actual, expected);
+
+        let mut lines = bounded_lines(file.as_bytes(), 10);
+        lines.by_ref().for_each(drop);
+        assert_eq!(lines.too_long(), [11..10_000_013, 10_000_024..10_000_037]);
+        assert_eq!(&file[11..10_000_013], format!("{long_line}\r\n"));
+    }
+
     #[test]
     fn test_line_bad_split_too_many() {
         let actual = Line::parse("2ZaOGztkpc                                                      foo bar").unwrap_err();
@@ -0,0 +1,143 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Single-instance locking, scoped to a moderations directory
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// how long to wait between attempts to take a lock held by another instance
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Held for as long as we're modifying a moderations directory. The OS lock is released on drop.
+pub struct DirectoryLock {
+    _file: File,
+}
+
+impl DirectoryLock {
+    /// Try to lock `moderations_dir`, using a lock file in `data_dir`. This does not wait if another instance holds the lock.
+    /// `data_dir` is created if it does not exist yet, as is the case on a fresh install.
+    pub fn acquire(data_dir: &Path, moderations_dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(data_dir).map_err(Error::Io)?;
+        let file = {
+            let mut open_options = OpenOptions::new();
+            open_options.write(true);
+            open_options.create(true);
+            open_options.truncate(false);
+            open_options.open(lock_path(data_dir, moderations_dir)).map_err(Error::Io)?
+        };
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(Error::WouldBlock),
+            Err(TryLockError::Error(e)) => Err(Error::Io(e)),
+        }
+    }
+
+    /// Like [`Self::acquire`], but while another instance holds the lock keep trying until `timeout` has passed.
+    /// `on_retry` is called with the time waited so far before each retry.
+    pub fn acquire_with_timeout<F: FnMut(Duration)>(data_dir: &Path, moderations_dir: &Path, timeout: Duration, mut on_retry: F) -> Result<Self, Error> {
+        let start = Instant::now();
+        loop {
+            match Self::acquire(data_dir, moderations_dir) {
+                Err(Error::WouldBlock) if start.elapsed() < timeout => {
+                    thread::sleep(RETRY_INTERVAL.min(timeout.saturating_sub(start.elapsed())));
+                    on_retry(start.elapsed());
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Path to the lock file for a moderations directory. Different spellings of the same directory map to the same lock
+/// file, and different directories map to different lock files.
+pub fn lock_path(data_dir: &Path, moderations_dir: &Path) -> PathBuf {
+    let moderations_dir = fs::canonicalize(moderations_dir).unwrap_or_else(|_| moderations_dir.to_path_buf());
+    data_dir.join(format!("hooligan-{:016x}.lock", crate::fnv1a(crate::FNV1A_EMPTY, moderations_dir.as_os_str().as_encoded_bytes())))
+}
+
+#[allow(dead_code)] // lint misses usage in debug printing this error
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// another instance is holding the lock
+    WouldBlock,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn test_lock_different_directories() {
+        let dir = TestDir::new("lock-different");
+        let data_dir = dir.join("data");
+        let moderations_a = dir.join("a");
+        let moderations_b = dir.join("b");
+        for path in [&data_dir, &moderations_a, &moderations_b] {
+            fs::create_dir_all(path).unwrap();
+        }
+
+        assert_ne!(lock_path(&data_dir, &moderations_a), lock_path(&data_dir, &moderations_b));
+        let _lock_a = DirectoryLock::acquire(&data_dir, &moderations_a).unwrap();
+        let _lock_b = DirectoryLock::acquire(&data_dir, &moderations_b).unwrap();
+    }
+
+    #[test]
+    fn test_lock_missing_data_directory() {
+        let dir = TestDir::new("lock-missing-data");
+        let data_dir = dir.join("data").join("nested");
+        let moderations = dir.join("a");
+        fs::create_dir_all(moderations.as_path()).unwrap();
+
+        let _lock = DirectoryLock::acquire(&data_dir, &moderations).unwrap();
+        assert!(lock_path(&data_dir, &moderations).is_file());
+    }
+
+    #[test]
+    fn test_lock_same_directory() {
+        let dir = TestDir::new("lock-same");
+        let data_dir = dir.join("data");
+        let moderations = dir.join("a");
+        for path in [&data_dir, &moderations] {
+            fs::create_dir_all(path).unwrap();
+        }
+
+        let lock = DirectoryLock::acquire(&data_dir, &moderations).unwrap();
+        assert!(matches!(DirectoryLock::acquire(&data_dir, &moderations), Err(Error::WouldBlock)));
+        drop(lock);
+        let _lock = DirectoryLock::acquire(&data_dir, &moderations).unwrap();
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let dir = TestDir::new("lock-timeout");
+        let data_dir = dir.join("data");
+        let moderations = dir.join("a");
+        for path in [&data_dir, &moderations] {
+            fs::create_dir_all(path).unwrap();
+        }
+
+        let lock = DirectoryLock::acquire(&data_dir, &moderations).unwrap();
+        let mut retries = 0;
+        let result = DirectoryLock::acquire_with_timeout(&data_dir, &moderations, Duration::ZERO, |_| retries += 1);
+        assert!(matches!(result, Err(Error::WouldBlock)));
+        assert_eq!(retries, 0);
+        let result = DirectoryLock::acquire_with_timeout(&data_dir, &moderations, RETRY_INTERVAL * 2, |_| retries += 1);
+        assert!(matches!(result, Err(Error::WouldBlock)));
+        assert_eq!(retries, 2);
+
+        // released by another instance while we wait
+        let releaser = thread::spawn(move || {
+            thread::sleep(RETRY_INTERVAL);
+            drop(lock);
+        });
+        let _lock = DirectoryLock::acquire_with_timeout(&data_dir, &moderations, Duration::from_secs(60), |_| {}).unwrap();
+        releaser.join().unwrap();
+    }
+}
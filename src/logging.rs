@@ -6,49 +6,430 @@
 
 use std::{fmt, fs, io};
 use std::fmt::{Display, Formatter};
-use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
+use chrono::{DateTime, Local, Offset, TimeZone};
 use directories::ProjectDirs;
-use file_rotate::{ContentLimit, FileRotate};
-use file_rotate::suffix::AppendCount;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 
-type LogWrite = BufWriter<FileRotate<AppendCount>>;
+use crate::json;
 
+/// default for the `max_log_files` config key
+pub const DEFAULT_MAX_LOG_FILES: usize = 7;
+/// default for the `max_log_file_size_bytes` config key
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024 * 10;
+const LOG_PREFIX: &str = "hooligan.";
+const LOG_EXTENSION: &str = ".log";
+/// added to the name of a log file once it is compressed
+const GZIP_EXTENSION: &str = ".gz";
+
+const ERROR: &str = "error";
+const INFO: &str = "info";
+const DEBUG: &str = "debug";
+const TEXT: &str = "text";
+const JSON: &str = "json";
+const EPOCH: &str = "epoch";
+const ISO: &str = "iso";
+
+/// How much detail goes in the log. Each level includes everything logged at the levels before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            ERROR => Some(Self::Error),
+            INFO => Some(Self::Info),
+            DEBUG => Some(Self::Debug),
+            _ => None,
+        }
+    }
+
+    pub const fn serialize(self) -> &'static str {
+        match self {
+            Self::Error => ERROR,
+            Self::Info => INFO,
+            Self::Debug => DEBUG,
+        }
+    }
+}
+
+/// How each log line is laid out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `<timestamp>: <message>`
+    Text,
+    /// one `{"ts":<timestamp>,"level":"<level>","msg":"<message>"}` object per line. An [`Timestamp::Iso`] timestamp
+    /// is a JSON string.
+    Json,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            TEXT => Some(Self::Text),
+            JSON => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    pub const fn serialize(self) -> &'static str {
+        match self {
+            Self::Text => TEXT,
+            Self::Json => JSON,
+        }
+    }
+}
+
+/// How the time each log line was written is shown
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timestamp {
+    /// unix seconds, for logs read by other programs
+    Epoch,
+    /// RFC 3339 in the local time zone, like `2025-01-15T14:03:22-05:00`
+    Iso,
+}
+
+impl Timestamp {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            EPOCH => Some(Self::Epoch),
+            ISO => Some(Self::Iso),
+            _ => None,
+        }
+    }
+
+    pub const fn serialize(self) -> &'static str {
+        match self {
+            Self::Epoch => EPOCH,
+            Self::Iso => ISO,
+        }
+    }
+}
+
+/// Where log lines go. Normally this is a rotating log file, but anything that can be written to will do.
 pub struct LogFile {
-    write: LogWrite,
+    target: Target,
+    level: Level,
+    format: Format,
+    timestamp: Timestamp,
+}
+
+enum Target {
+    Writer(Box<dyn Write + Send>),
+    /// lines held back until they are appended to another log
+    Memory(Vec<u8>),
+}
+
+impl Target {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Self::Writer(write) => write,
+            Self::Memory(lines) => lines,
+        }
+    }
 }
 
 impl LogFile {
-    fn new(write: LogWrite) -> Self {
-        Self { write }
+    pub fn new<W: Write + Send + 'static>(write: W) -> Self {
+        Self {
+            target: Target::Writer(Box::new(write)),
+            level: Level::Info,
+            format: Format::Text,
+            timestamp: Timestamp::Iso,
+        }
     }
 
-    /// evil hack to write timestamps in logs
+    /// A log that holds its lines in memory until they are passed to [`Self::append`], for use before it's known where
+    /// the log file goes
+    pub const fn in_memory() -> Self {
+        Self {
+            target: Target::Memory(Vec::new()),
+            level: Level::Info,
+            format: Format::Text,
+            timestamp: Timestamp::Iso,
+        }
+    }
+
+    /// A log with the same settings as this one that holds its lines in memory until they are passed to
+    /// [`Self::append`]. This keeps lines from work done on other threads together and in a predictable order.
+    pub const fn deferred(&self) -> Self {
+        Self {
+            target: Target::Memory(Vec::new()),
+            level: self.level,
+            format: self.format,
+            timestamp: self.timestamp,
+        }
+    }
+
+    /// Write out the lines held by a [`Self::deferred`] log
+    pub fn append(&mut self, deferred: Self) {
+        if let Target::Memory(lines) = deferred.target {
+            self.target.writer().write_all(&lines).expect("failed to write deferred log lines");
+        }
+    }
+
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    pub fn set_level(&mut self, level: Level) {
+        self.level = level;
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: Timestamp) {
+        self.timestamp = timestamp;
+    }
+
+    /// Write one line at `level`, unless the log is set to be less verbose than that
+    pub fn log(&mut self, level: Level, args: fmt::Arguments<'_>) {
+        if level <= self.level {
+            self.write_line(level, format_args!("{args}\n"));
+        }
+    }
+
+    /// evil hack to write timestamps in logs. Lines written with `writeln!` are logged at [`Level::Info`].
     pub fn write_fmt(&mut self, args: fmt::Arguments<'_>) {
-        write!(self.write, "{}: ", CurrentTime).expect("failed to write log timestamp");
-        self.write.write_fmt(args).expect("failed to write log arguments");
+        if Level::Info <= self.level {
+            self.write_line(Level::Info, args);
+        }
+    }
+
+    /// `args` must already end in a newline
+    fn write_line(&mut self, level: Level, args: fmt::Arguments<'_>) {
+        let current_time = CurrentTime::now(self.timestamp);
+        match self.format {
+            Format::Text => {
+                let write = self.target.writer();
+                write!(write, "{current_time}: ").expect("failed to write log timestamp");
+                write.write_fmt(args).expect("failed to write log arguments");
+            }
+            Format::Json => {
+                // the message has to be rendered up front to be escaped
+                let message = args.to_string();
+                let message = message.strip_suffix('\n').unwrap_or(&message);
+                let ts = match self.timestamp {
+                    Timestamp::Epoch => current_time.to_string(),
+                    Timestamp::Iso => json::string(&current_time.to_string()),
+                };
+                writeln!(self.target.writer(), "{{\"ts\":{},\"level\":{},\"msg\":{}}}", ts, json::string(level.serialize()), json::string(message))
+                    .expect("failed to write log line");
+            }
+        }
     }
 
     pub fn flush(&mut self) -> io::Result<()> {
-        self.write.flush()
+        self.target.writer().flush()
+    }
+}
+
+/// Open today's log file for appending, then compress the other log files and delete the oldest beyond `max_files`.
+/// Today's file is only reused while it's smaller than `max_file_size`.
+pub fn get_logger(project_dirs: &ProjectDirs, max_files: usize, max_file_size: u64) -> io::Result<LogFile> {
+    let log_dir_path = create_log_dir_path(project_dirs)?;
+    let current = get_log_file(log_dir_path.as_path(), Date::today(), max_file_size)?;
+    let file = {
+        let mut open_options = OpenOptions::new();
+        open_options.create(true);
+        open_options.append(true);
+        open_options.open(log_dir_path.join(current.to_string()))?
+    };
+    let mut log = LogFile::new(BufWriter::new(file));
+    // failing to tidy up old logs is no reason not to log
+    if let Err(e) = rotate_log_files(log_dir_path.as_path(), current, max_files) {
+        log.log(Level::Error, format_args!("error rotating old log files: {e:?}"));
+    }
+    Ok(log)
+}
+
+/// Open a log file for reading, decompressing it if needed
+pub fn read_log_file(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    if path.as_os_str().as_encoded_bytes().ends_with(GZIP_EXTENSION.as_bytes()) {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// All log files, including older ones and any written before logs were named by date
+pub fn get_log_files(project_dirs: &ProjectDirs) -> io::Result<Vec<PathBuf>> {
+    let log_dir_path = get_log_dir(project_dirs);
+    let mut log_files = Vec::new();
+    if log_dir_path.is_dir() {
+        for dir_entry in fs::read_dir(log_dir_path)? {
+            let dir_entry = dir_entry?;
+            if dir_entry.file_name().as_encoded_bytes().starts_with(LOG_PREFIX.as_bytes()) {
+                log_files.push(dir_entry.path());
+            }
+        }
+    }
+    Ok(log_files)
+}
+
+/// Pick the file to log to on `today`: the newest file from today if it has room, otherwise a new one
+fn get_log_file(log_dir_path: &Path, today: Date, max_file_size: u64) -> io::Result<LogName> {
+    let log_files = read_log_names(log_dir_path)?;
+    let newest = log_files.last().filter(|(name, _)| name.date == today);
+    let current = match newest {
+        Some((name, path)) if !name.compressed && fs::metadata(path)?.len() < max_file_size => *name,
+        Some((name, _)) => LogName { date: today, part: name.part + 1, compressed: false },
+        None => LogName { date: today, part: 0, compressed: false },
+    };
+    Ok(current)
+}
+
+/// Delete the oldest log files beyond `max_files`, and compress the rest except for `current`
+fn rotate_log_files(log_dir_path: &Path, current: LogName, max_files: usize) -> io::Result<()> {
+    let log_files = read_log_names(log_dir_path)?;
+    // current is the newest, so keeping at least one file keeps it
+    let expired = expired(&log_files, max_files.max(1));
+    for (_, path) in expired {
+        fs::remove_file(path)?;
+    }
+    for (name, path) in &log_files[expired.len()..] {
+        if !name.compressed && *name != current {
+            compress(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Replace a file with a gzipped copy named with an added `.gz`
+fn compress(path: &Path) -> io::Result<()> {
+    let mut compressed_path = path.as_os_str().to_owned();
+    compressed_path.push(GZIP_EXTENSION);
+    let mut encoder = GzEncoder::new(File::create(compressed_path)?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(path)
+}
+
+/// Log files named by date in `log_dir_path`, oldest first
+fn read_log_names(log_dir_path: &Path) -> io::Result<Vec<(LogName, PathBuf)>> {
+    let mut log_files: Vec<(LogName, PathBuf)> = Vec::new();
+    for dir_entry in fs::read_dir(log_dir_path)? {
+        let dir_entry = dir_entry?;
+        if let Some(name) = dir_entry.file_name().to_str().and_then(LogName::parse) {
+            log_files.push((name, dir_entry.path()));
+        }
     }
+    log_files.sort_unstable_by_key(|(name, _)| *name);
+    Ok(log_files)
 }
 
-pub fn get_logger(project_dirs: &ProjectDirs) -> io::Result<LogFile> {
-    let file_rotate = FileRotate::new(
-        get_log_file_prefix(project_dirs)?,
-        AppendCount::new(3),
-        ContentLimit::BytesSurpassed(1024 * 1024 * 10),
-    );
-    Ok(LogFile::new(BufWriter::new(file_rotate)))
+/// The oldest of `log_files` beyond the newest `max_files`. `log_files` must be sorted oldest first.
+fn expired<T>(log_files: &[T], max_files: usize) -> &[T] {
+    &log_files[..log_files.len().saturating_sub(max_files)]
 }
 
-fn get_log_file_prefix(project_dirs: &ProjectDirs) -> io::Result<PathBuf> {
-    let mut log_file_prefix_path = create_log_dir_path(project_dirs)?;
-    log_file_prefix_path.push("hooligan.log");
-    Ok(log_file_prefix_path)
+/// The name of a log file: `hooligan.<date>.log` for the first file of a day, then `hooligan.<date>.<part>.log` for
+/// any that follow once it fills up. Files no longer in use get a `.gz` on the end. Orders oldest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct LogName {
+    date: Date,
+    part: u32,
+    compressed: bool,
+}
+
+impl LogName {
+    fn parse(filename: &str) -> Option<Self> {
+        let (filename, compressed) = match filename.strip_suffix(GZIP_EXTENSION) {
+            Some(filename) => (filename, true),
+            None => (filename, false),
+        };
+        let name = filename.strip_prefix(LOG_PREFIX)?.strip_suffix(LOG_EXTENSION)?;
+        let (date, part) = match name.split_once('.') {
+            Some((date, part)) => (date, part.parse().ok().filter(|part| *part != 0)?),
+            None => (name, 0),
+        };
+        Some(Self {
+            date: Date::parse(date)?,
+            part,
+            compressed,
+        })
+    }
+}
+
+impl Display for LogName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.part == 0 {
+            write!(f, "{LOG_PREFIX}{}{LOG_EXTENSION}", self.date)?;
+        } else {
+            write!(f, "{LOG_PREFIX}{}.{}{LOG_EXTENSION}", self.date, self.part)?;
+        }
+        if self.compressed {
+            write!(f, "{GZIP_EXTENSION}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A calendar date, written as `YYYY-MM-DD`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Date {
+    year: u64,
+    month: u64,
+    day: u64,
+}
+
+impl Date {
+    /// today in the local time zone, so that a log file is named for the same day as the timestamps on its lines
+    fn today() -> Self {
+        Self::of(&Local::now())
+    }
+
+    /// the date of `date_time` in its own time zone
+    fn of<Tz: TimeZone>(date_time: &DateTime<Tz>) -> Self {
+        let local_seconds = date_time.timestamp() + i64::from(date_time.offset().fix().local_minus_utc());
+        Self::from_unix_days(u64::try_from(local_seconds / (60 * 60 * 24)).unwrap_or(0))
+    }
+
+    /// Convert days since 1970-01-01 to a date, using Howard Hinnant's `civil_from_days`
+    fn from_unix_days(days: u64) -> Self {
+        let days = days + 719_468; // shift the epoch to 0000-03-01, so leap days fall at the end of each year
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153; // 0 is March
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+        Self {
+            year,
+            month,
+            day,
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let mut fields = value.splitn(3, '-');
+        let year = fields.next().filter(|year| year.len() == 4)?;
+        let month = fields.next().filter(|month| month.len() == 2)?;
+        let day = fields.next().filter(|day| day.len() == 2)?;
+        let date = Self {
+            year: year.parse().ok()?,
+            month: month.parse().ok()?,
+            day: day.parse().ok()?,
+        };
+        ((1..=12).contains(&date.month) && (1..=31).contains(&date.day)).then_some(date)
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
 }
 
 fn create_log_dir_path(project_dirs: &ProjectDirs) -> io::Result<PathBuf> {
@@ -61,14 +442,278 @@ fn get_log_dir(project_dirs: &ProjectDirs) -> PathBuf {
     project_dirs.data_local_dir().join("logs")
 }
 
-/// Handles displaying the current time in a minimally expensive way
-struct CurrentTime;
+/// Handles displaying the current time in a minimally expensive way. The clock is read once per line.
+struct CurrentTime<Tz: TimeZone = Local> {
+    /// unix seconds, negative if the clock is set before 1970
+    seconds: i64,
+    timestamp: Timestamp,
+    time_zone: Tz,
+}
+
+impl CurrentTime {
+    fn now(timestamp: Timestamp) -> Self {
+        let seconds = match SystemTime::UNIX_EPOCH.elapsed() {
+            Ok(current_time) => i64::try_from(current_time.as_secs()).unwrap_or(i64::MAX),
+            Err(e) => i64::try_from(e.duration().as_secs()).map_or(i64::MIN, |seconds| -seconds),
+        };
+        Self {
+            seconds,
+            timestamp,
+            time_zone: Local,
+        }
+    }
+}
 
-impl Display for CurrentTime {
+impl<Tz: TimeZone> Display for CurrentTime<Tz> where Tz::Offset: Display {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match SystemTime::UNIX_EPOCH.elapsed() {
-            Ok(current_time) => write!(f, "{}", current_time.as_secs()),
-            Err(e) => write!(f, "-{}", e.duration().as_secs())
+        let date_time = DateTime::from_timestamp(self.seconds, 0).filter(|_| self.timestamp == Timestamp::Iso);
+        match date_time {
+            Some(date_time) => write!(f, "{}", date_time.with_timezone(&self.time_zone).format("%Y-%m-%dT%H:%M:%S%:z")),
+            // also used for times too far from 1970 for a calendar date
+            None => write!(f, "{}", self.seconds),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use chrono::FixedOffset;
+
+    use super::*;
+    use crate::test_util::TestDir;
+
+    /// messages logged to a deferred log, without their timestamps
+    fn messages(log: &LogFile) -> Vec<String> {
+        let Target::Memory(lines) = &log.target else {
+            panic!("not a deferred log");
+        };
+        String::from_utf8(lines.clone()).unwrap().lines()
+            .map(|line| line.split_once(": ").map_or(line, |(_, message)| message).to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_log_level() {
+        let mut log = LogFile::new(io::sink()).deferred();
+        log.set_level(Level::Error);
+        log.log(Level::Error, format_args!("bad"));
+        writeln!(log, "routine");
+        log.log(Level::Debug, format_args!("detail"));
+        log.set_level(Level::Debug);
+        log.log(Level::Debug, format_args!("more detail"));
+        assert_eq!(messages(&log), vec!["bad", "more detail"]);
+    }
+
+    #[test]
+    fn test_log_json() {
+        let mut log = LogFile::new(io::sink());
+        log.set_format(Format::Json);
+        log.set_timestamp(Timestamp::Epoch);
+        let mut log = log.deferred();
+        writeln!(log, "external changes to \"a.vrcset\":\nshown usr_a\n");
+        log.log(Level::Error, format_args!("C:\\path\ttab"));
+
+        let Target::Memory(contents) = &log.target else {
+            panic!("not a deferred log");
+        };
+        let contents = String::from_utf8(contents.clone()).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let (ts, rest) = lines[0].strip_prefix("{\"ts\":").unwrap().split_once(',').unwrap();
+        assert!(ts.parse::<u64>().is_ok());
+        assert_eq!(rest, r#""level":"info","msg":"external changes to \"a.vrcset\":\nshown usr_a\n"}"#);
+        assert!(lines[1].ends_with(r#","level":"error","msg":"C:\\path\ttab"}"#));
+    }
+
+    #[test]
+    fn test_current_time() {
+        let current_time = |seconds, timestamp, offset_hours| CurrentTime {
+            seconds,
+            timestamp,
+            time_zone: FixedOffset::east_opt(offset_hours * 60 * 60).unwrap(),
+        }.to_string();
+        assert_eq!(current_time(1_736_967_802, Timestamp::Iso, -5), "2025-01-15T14:03:22-05:00");
+        assert_eq!(current_time(1_736_967_802, Timestamp::Iso, 0), "2025-01-15T19:03:22+00:00");
+        assert_eq!(current_time(1_736_967_802, Timestamp::Epoch, -5), "1736967802");
+        // a clock set before 1970
+        assert_eq!(current_time(-1, Timestamp::Iso, 0), "1969-12-31T23:59:59+00:00");
+        assert_eq!(current_time(-1, Timestamp::Epoch, 0), "-1");
+        // too far out for a calendar date
+        assert_eq!(current_time(i64::MIN, Timestamp::Iso, 0), i64::MIN.to_string());
+    }
+
+    #[test]
+    fn test_log_json_iso() {
+        let mut log = LogFile::new(io::sink());
+        log.set_format(Format::Json);
+        let mut log = log.deferred();
+        writeln!(log, "hello");
+
+        let Target::Memory(contents) = &log.target else {
+            panic!("not a deferred log");
+        };
+        let contents = String::from_utf8(contents.clone()).unwrap();
+        let (ts, rest) = contents.strip_prefix("{\"ts\":\"").unwrap().split_once('"').unwrap();
+        assert!(DateTime::parse_from_rfc3339(ts).is_ok(), "{ts}");
+        assert_eq!(rest, ",\"level\":\"info\",\"msg\":\"hello\"}\n");
+    }
+
+    #[test]
+    fn test_log_append() {
+        let mut log = LogFile::new(io::sink()).deferred();
+        let mut first = log.deferred();
+        let mut second = log.deferred();
+        writeln!(second, "second");
+        writeln!(first, "first");
+        writeln!(log, "before");
+        log.append(first);
+        log.append(second);
+        assert_eq!(messages(&log), vec!["before", "first", "second"]);
+    }
+
+    #[test]
+    fn test_date() {
+        assert_eq!(Date::from_unix_days(0).to_string(), "1970-01-01");
+        assert_eq!(Date::from_unix_days(11_016).to_string(), "2000-02-29");
+        assert_eq!(Date::from_unix_days(20_103).to_string(), "2025-01-15");
+        assert_eq!(Date::parse("2025-01-15"), Some(Date::from_unix_days(20_103)));
+        // late in the evening west of UTC, it's already the next day in UTC
+        let evening = DateTime::parse_from_rfc3339("2025-01-15T23:30:00-05:00").unwrap();
+        assert_eq!(Date::of(&evening), Date::from_unix_days(20_103));
+        assert_eq!(Date::of(&evening.with_timezone(&FixedOffset::east_opt(0).unwrap())), Date::from_unix_days(20_104));
+        for invalid in ["2025-1-15", "2025-13-01", "2025-01-00", "25-01-15", "2025-01-15-01", "2025-01-1x"] {
+            assert_eq!(Date::parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_log_name() {
+        let date = Date::from_unix_days(20_103);
+        for (filename, part, compressed) in [
+            ("hooligan.2025-01-15.log", 0, false),
+            ("hooligan.2025-01-15.2.log", 2, false),
+            ("hooligan.2025-01-15.log.gz", 0, true),
+            ("hooligan.2025-01-15.2.log.gz", 2, true),
+        ] {
+            let name = LogName::parse(filename).unwrap();
+            assert_eq!(name, LogName { date, part, compressed });
+            assert_eq!(name.to_string(), filename);
+        }
+        for invalid in ["hooligan.log", "hooligan.log.1", "hooligan.2025-01-15.0.log", "hooligan.2025-01-15.gz", "other.2025-01-15.log"] {
+            assert_eq!(LogName::parse(invalid), None, "{invalid}");
+        }
+        assert!(LogName { date, part: 2, compressed: true } < LogName { date: Date::from_unix_days(20_104), part: 0, compressed: false });
+    }
+
+    #[test]
+    fn test_expired() {
+        assert_eq!(expired(&[1, 2, 3, 4], 2), [1, 2]);
+        assert_eq!(expired(&[1, 2], 2), [] as [i32; 0]);
+        assert_eq!(expired(&[1], 2), [] as [i32; 0]);
+    }
+
+    #[test]
+    fn test_get_log_file() {
+        let dir = TestDir::new("log-files");
+        let today = Date::from_unix_days(20_103);
+        for days in 20_095..20_103 {
+            let name = LogName { date: Date::from_unix_days(days), part: 0, compressed: false };
+            fs::write(dir.join(name.to_string()), "old\n").unwrap();
+        }
+        fs::write(dir.join("hooligan.log"), "from before logs were named by date\n").unwrap();
+
+        // a new day gets a new file, the oldest files beyond the limit are deleted, and the rest are compressed
+        let current = get_log_file(dir.as_path(), today, DEFAULT_MAX_FILE_SIZE).unwrap();
+        assert_eq!(current.to_string(), "hooligan.2025-01-15.log");
+        fs::write(dir.join(current.to_string()), "today\n").unwrap();
+        rotate_log_files(dir.as_path(), current, DEFAULT_MAX_LOG_FILES).unwrap();
+        let mut remaining: Vec<String> = fs::read_dir(dir.as_path()).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        remaining.sort_unstable();
+        assert_eq!(remaining.len(), DEFAULT_MAX_LOG_FILES + 1); // today and the days before it, plus the legacy file which is left alone
+        assert_eq!(remaining.first().map(String::as_str), Some("hooligan.2025-01-09.log.gz"));
+        assert_eq!(remaining.iter().filter(|name| name.ends_with(".log.gz")).count(), DEFAULT_MAX_LOG_FILES - 1);
+        assert!(remaining.contains(&"hooligan.2025-01-15.log".to_owned()));
+
+        // today's file is reused until it fills up
+        assert_eq!(get_log_file(dir.as_path(), today, DEFAULT_MAX_FILE_SIZE).unwrap(), current);
+        File::create(dir.join(current.to_string())).unwrap().set_len(DEFAULT_MAX_FILE_SIZE).unwrap();
+        assert_eq!(get_log_file(dir.as_path(), today, DEFAULT_MAX_FILE_SIZE).unwrap().to_string(), "hooligan.2025-01-15.1.log");
+    }
+
+    #[test]
+    fn test_custom_limits() {
+        let dir = TestDir::new("log-limits");
+        let today = Date::from_unix_days(20_103);
+        for days in 20_100..20_103 {
+            let name = LogName { date: Date::from_unix_days(days), part: 0, compressed: false };
+            fs::write(dir.join(name.to_string()), "old\n").unwrap();
+        }
+        let first = LogName { date: today, part: 0, compressed: false };
+        fs::write(dir.join(first.to_string()), "1234").unwrap();
+
+        // a file is only reused while it's under the size limit
+        assert_eq!(get_log_file(dir.as_path(), today, 5).unwrap(), first);
+        let current = get_log_file(dir.as_path(), today, 4).unwrap();
+        assert_eq!(current, LogName { date: today, part: 1, compressed: false });
+        fs::write(dir.join(current.to_string()), "today\n").unwrap();
+
+        rotate_log_files(dir.as_path(), current, 2).unwrap();
+        let mut remaining: Vec<String> = fs::read_dir(dir.as_path()).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        remaining.sort_unstable();
+        assert_eq!(remaining, ["hooligan.2025-01-15.1.log", "hooligan.2025-01-15.log.gz"]);
+
+        // the current file is kept even with a limit of no files
+        rotate_log_files(dir.as_path(), current, 0).unwrap();
+        let remaining: Vec<String> = fs::read_dir(dir.as_path()).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        assert_eq!(remaining, ["hooligan.2025-01-15.1.log"]);
+    }
+
+    #[test]
+    fn test_compress() {
+        let dir = TestDir::new("compress");
+        let contents: Vec<u8> = (0..10_000u32).flat_map(|i| format!("line {i}\n").into_bytes()).collect();
+        let old = LogName { date: Date::from_unix_days(20_102), part: 0, compressed: false };
+        let current = LogName { date: Date::from_unix_days(20_103), part: 0, compressed: false };
+        fs::write(dir.join(old.to_string()), contents.as_slice()).unwrap();
+        fs::write(dir.join(current.to_string()), "today\n").unwrap();
+
+        rotate_log_files(dir.as_path(), current, DEFAULT_MAX_LOG_FILES).unwrap();
+        assert!(!dir.join(old.to_string()).exists());
+        assert!(dir.join(current.to_string()).exists());
+        let compressed_path = dir.join(LogName { compressed: true, ..old }.to_string());
+        let mut decompressed = Vec::new();
+        GzDecoder::new(File::open(compressed_path.as_path()).unwrap()).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, contents);
+
+        // reading a compressed log transparently decompresses it
+        let lines: Vec<String> = read_log_file(compressed_path.as_path()).unwrap().lines().map(Result::unwrap).collect();
+        assert_eq!(lines.len(), 10_000);
+        assert_eq!(lines.last().map(String::as_str), Some("line 9999"));
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        for format in [Format::Text, Format::Json] {
+            assert_eq!(Format::parse(format.serialize()), Some(format));
+        }
+        assert_eq!(Format::parse("xml"), None);
+    }
+
+    #[test]
+    fn test_timestamp_round_trip() {
+        for timestamp in [Timestamp::Epoch, Timestamp::Iso] {
+            assert_eq!(Timestamp::parse(timestamp.serialize()), Some(timestamp));
+        }
+        assert_eq!(Timestamp::parse("rfc3339"), None);
+    }
+
+    #[test]
+    fn test_level_round_trip() {
+        for level in [Level::Error, Level::Info, Level::Debug] {
+            assert_eq!(Level::parse(level.serialize()), Some(level));
         }
+        assert_eq!(Level::parse("verbose"), None);
     }
 }
@@ -4,45 +4,65 @@
 
 #![windows_subsystem = "windows"] // don't pop up a weird terminal window
 
-use std::{env, io};
+use std::{env, io, mem};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ffi::OsString;
-use std::fs::{self, DirEntry, File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::num::TryFromIntError;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitCode};
+use std::thread;
+use std::time::Duration;
 
 use directories::ProjectDirs;
 
-use crate::config::Config;
-use crate::local_player_moderations as moderation;
-use crate::transaction::{Transaction, Value as TransactionValue};
+use hooligan::{cli, config, csv, current_timestamp, json, lock, logging, platform, run, transaction, Error};
+use hooligan::cli::Args;
+use hooligan::config::Config;
+use hooligan::external_changes::ExternalChanges;
+use hooligan::local_player_moderations as moderation;
+use hooligan::timeline::Timeline;
+use hooligan::lock::DirectoryLock;
+use hooligan::report::{KeyHistory, StuckUser};
+use hooligan::logging::Level;
+use hooligan::run::{NamedVrcset, RunStats};
+use hooligan::transaction::{ShowHideCount, Transaction, Value as TransactionValue};
 
-mod local_player_moderations;
-mod logging;
-mod transaction;
-mod config;
+/// environment variable that replaces the usual LocalPlayerModerations path, e.g. for Proton or testing
+const MODERATIONS_DIR_VAR: &str = "HOOLIGAN_MODERATIONS_DIR";
+
+/// delay before the first retry of a failed launch. Each later retry waits this much longer than the last.
+const LAUNCH_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// exit code when the cleanup was skipped because VRChat was already running
+const VRCHAT_RUNNING_EXIT_CODE: u8 = 2;
+
+/// exit code when the cleanup was skipped because another instance held the lock for longer than `lock_timeout_secs`
+const LOCK_TIMEOUT_EXIT_CODE: u8 = 3;
+
+fn main() -> ExitCode {
+    // args are parsed before anything else, as the profile determines where everything else lives
+    let args = Args::parse(env::args_os().skip(1)); // we skip the first arg because it's just a path to this executable
+    // these just print, without touching config, logs, or anything else on disk
+    if let Ok(args) = &args {
+        if args.help {
+            print!("{}", cli::usage());
+            return ExitCode::SUCCESS;
+        }
+        if args.version {
+            println!("{} {} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), env!("GIT_COMMIT_HASH"));
+            return ExitCode::SUCCESS;
+        }
+    }
+    let profile = args.as_ref().ok().and_then(|args| args.profile.as_deref());
 
-fn main() {
     // toss some global-state type things into a struct to make them easier to access
-    let project_dirs = get_project_dirs().expect("failed to get project directory");
-    let log = logging::get_logger(&project_dirs).expect("failed to open log file for writing");
+    let project_dirs = get_project_dirs(profile).expect("failed to get project directory");
     Hooligan {
-        log,
+        // lines are held until the config has been read and the log file is opened
+        log: logging::LogFile::in_memory(),
         project_dirs,
-    }.run();
-}
-
-#[allow(dead_code)] // lint misses usage in debug printing this error
-#[derive(Debug)]
-enum Error {
-    Io(io::Error),
-    ShowHideParse(moderation::ParseError),
-    TransactionParse(transaction::ParseError),
-    EnvironmentVar(env::VarError),
-    U64FromInt(TryFromIntError),
-    BadFilename(OsString),
-    ConfigLoad(config::Error),
+    }.run(args)
 }
 
 struct Hooligan {
@@ -51,232 +71,603 @@ struct Hooligan {
 }
 
 impl Hooligan {
-    fn run(mut self) {
-        match self.run_checked() {
-            Ok(()) => writeln!(self.log, "done"),
-            Err(e) => writeln!(self.log, "{e:?}"),
-        }
+    fn run(mut self, args: Result<Args, cli::Error>) -> ExitCode {
+        let exit_code = match self.run_checked(args) {
+            Ok(()) => {
+                writeln!(self.log, "done");
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                self.log.log(Level::Error, format_args!("{e:?}"));
+                exit_code(&e)
+            }
+        };
         self.log.flush().expect("failed to flush log buffer to disk");
+        exit_code
     }
 
-    fn run_checked(&mut self) -> Result<(), Error> {
+    fn run_checked(&mut self, args: Result<Args, cli::Error>) -> Result<(), Error> {
         writeln!(self.log, "starting {} version {} {}",
                  env!("CARGO_PKG_NAME"),
                  env!("CARGO_PKG_VERSION"),
                  env!("GIT_COMMIT_HASH"));
 
-        // read config
-        let config = self.load_config();
+        // read config before opening the log file, as the config decides how log files are kept
+        let mut config = self.load_config();
+        if let Ok(args) = &args {
+            for config_override in &args.config_overrides {
+                match config.apply_override(config_override) {
+                    Ok(()) => writeln!(self.log, "override {config_override}"),
+                    Err(e) => writeln!(self.log, "ignoring invalid override {config_override}: {e:?}"),
+                }
+            }
+            if let Some(threshold) = args.threshold {
+                config.auto_hide_threshold = threshold;
+            }
+        }
+        self.open_log(&config);
+        let args = args.map_err(Error::Args)?;
+
+        self.log.set_level(config.log_level);
+        self.log.set_format(config.log_format);
+        self.log.set_timestamp(config.log_timestamp);
+        writeln!(self.log, "using auto_hide_threshold {}", config.auto_hide_threshold);
+        match config.auto_hide_threshold {
+            0 => writeln!(self.log, "auto hide is disabled because auto_hide_threshold is 0"),
+            1 => writeln!(self.log, "WARNING: auto_hide_threshold 1 keeps every user the first time they're shown, the same as disabling auto hide with 0"),
+            _ => {}
+        }
+
+        // the windows subsystem means there's never a console to ask in, so --confirm would apply every change without asking
+        if cfg!(windows) && args.confirm {
+            writeln!(self.log, "--confirm is not supported on Windows, where hooligan has no console to ask in; use --dry-run or --diff to review changes instead");
+            return Err(Error::ConfirmUnsupported);
+        }
+
+        if args.status {
+            return self.report_status(&config, args.json);
+        }
+
+        if let Some(key) = &args.history {
+            return self.report_history(key, &config, args.json);
+        }
+
+        if let Some(export_path) = &args.export_csv {
+            return self.export_csv(export_path.as_path(), &config);
+        }
+
+        if let Some(export_path) = &args.export_allowlist {
+            return self.export_allowlist(export_path.as_path(), &config, args.json);
+        }
+
+        let moderations_path = match &args.moderations_dir {
+            Some(moderations_dir) => moderations_dir.clone(),
+            None => get_local_player_moderations_path()?,
+        };
+        if moderations_path.is_file() {
+            writeln!(self.log, "{} is a file, but hooligan needs the folder holding your .vrcset files. Check {MODERATIONS_DIR_VAR} and --moderations-dir.",
+                     moderations_path.display());
+            return Err(Error::ModerationsPathIsFile(moderations_path));
+        }
+        if args.moderations_dir.is_some() && !moderations_path.is_dir() {
+            return Err(Error::ModerationsPathNotFound(moderations_path));
+        }
+
+        if let Some(name) = &args.external_changes {
+            return self.report_external_changes(moderations_path.as_path(), name, &config, args.json);
+        }
+
+        if let Some(name) = &args.timeline {
+            return self.report_timeline(name, args.json);
+        }
+
+        // VRChat writes its .vrcset files while it runs, and the lock below can't keep it out, so leave them alone
+        if !(args.dry_run || args.reset_all) {
+            match platform::vrchat_running(&platform::System) {
+                Ok(false) => {}
+                Ok(true) => {
+                    writeln!(self.log, "{} is already running; skipping cleanup so we don't clobber its changes", platform::VRCHAT_PROCESS_NAME);
+                    // skip the cleanup, but still launch the game
+                    if !(args.undo || args.import_hide.is_some() || args.reset.is_some()) {
+                        self.spawn_process(&args, &config)?;
+                    }
+                    return Err(Error::VrchatRunning);
+                }
+                Err(e) => self.log.log(Level::Error, format_args!("failed to check whether {} is running, continuing anyway: {e:?}", platform::VRCHAT_PROCESS_NAME)),
+            }
+        }
+
+        // only one instance may modify a given moderations directory at a time
+        self.warn_on_directory_overlap(moderations_path.as_path());
+        // the lock lives outside any profile, so that profiles sharing a moderations directory still exclude each other
+        let lock_dir = get_project_dirs(None).map_err(Error::Io)?;
+        let lock_timeout = Duration::from_secs(config.lock_timeout_secs);
+        let on_retry = |waited: Duration| self.log.log(Level::Debug, format_args!("lock is held by another instance; retrying after {waited:?}"));
+        let lock = match DirectoryLock::acquire_with_timeout(lock_dir.data_local_dir(), moderations_path.as_path(), lock_timeout, on_retry) {
+            Ok(lock) => lock,
+            Err(e) => {
+                // skip the cleanup, but still launch the game
+                if !(args.reset_all || args.undo || args.import_hide.is_some() || args.reset.is_some()) {
+                    self.spawn_process(&args, &config)?;
+                }
+                return Err(Error::Lock(e));
+            }
+        };
+
+        if args.reset_all {
+            let result = self.reset_all(&config, args.dry_run);
+            drop(lock);
+            return result;
+        }
+
+        if args.undo {
+            let undone = run::undo_all(&config, moderations_path.as_path(), self.history_dir().as_path(), &mut self.log)?;
+            drop(lock);
+            writeln!(self.log, "undid the last run of {undone} files");
+            return Ok(());
+        }
 
-        // iterate over all *.vrcset files
-        let read_dir = fs::read_dir(get_local_player_moderations_path()?).map_err(Error::Io)?;
-        for dir_entry in read_dir {
-            let dir_entry = dir_entry.map_err(Error::Io)?;
-            self.process_file(dir_entry, &config)?;
+        if let Some(import_path) = &args.import_hide {
+            let keys = run::read_hide_list(import_path.as_path())?;
+            let stats = run::import_hides(&config, moderations_path.as_path(), self.history_dir().as_path(), &keys, &mut self.log)?;
+            drop(lock);
+            writeln!(self.log, "imported {} users from {}: hid {} users, {} already hidden",
+                     keys.len(), import_path.display(), stats.added, stats.present);
+            return Ok(());
+        }
+
+        if let Some(key) = &args.reset {
+            let found = run::reset_user(&config, moderations_path.as_path(), self.history_dir().as_path(), key, &mut self.log)?;
+            drop(lock);
+            if found == 0 {
+                writeln!(self.log, "{key} was not found in any file; nothing to reset");
+            } else {
+                writeln!(self.log, "reset {key} in {found} files");
+            }
+            return Ok(());
         }
 
+        let options = run::Options {
+            dry_run: args.dry_run,
+            confirm: args.confirm,
+            diff: args.diff,
+        };
+        let stats = run::run_all(&config, moderations_path.as_path(), self.history_dir().as_path(), &options, &mut self.log)?;
+        // when cleaning up again after VRChat exits, nothing else may touch the moderations directory in the meantime
+        let lock = if config.rerun_after_exit {
+            Some(lock)
+        } else {
+            drop(lock);
+            None
+        };
+        self.report_run(&stats, &options, &config);
+
         // launch the VRChat process
-        self.spawn_process()?;
+        let child = self.spawn_process(&args, &config)?;
+
+        if let Some((lock, mut child)) = lock.zip(child) {
+            writeln!(self.log, "waiting for process {} to exit", child.id());
+            // this could be a while, so get everything so far onto disk
+            self.log.flush().map_err(Error::Io)?;
+            let status = child.wait().map_err(Error::Io)?;
+            writeln!(self.log, "launched process exited with {status}; cleaning up again");
+            let stats = run::run_all(&config, moderations_path.as_path(), self.history_dir().as_path(), &options, &mut self.log)?;
+            drop(lock);
+            self.report_run(&stats, &options, &config);
+        }
 
         Ok(())
     }
 
+    fn report_run(&mut self, stats: &RunStats, options: &run::Options, config: &Config) {
+        writeln!(self.log, "processed {} files: removed {}, retained {}, pruned {}, and added {} entries",
+                 stats.files.len(), stats.removed(), stats.retained(), stats.pruned(), stats.shown());
+        if options.diff {
+            if let Err(e) = io::stdout().write_all(stats.diff_report().as_bytes()) {
+                self.log.log(Level::Error, format_args!("error printing diff: {e:?}"));
+            }
+        }
+        if !options.dry_run {
+            self.write_summary(stats);
+            if config.notify {
+                self.notify(stats);
+            }
+        }
+    }
+
+    /// Pop up a notification with the run's counts, as hooligan has no window of its own. This is purely informational,
+    /// so failures are logged and otherwise ignored.
+    fn notify(&mut self, stats: &RunStats) {
+        let text = format!("removed {}, retained {}, and auto-shown {} across {} files",
+                           stats.removed(), stats.retained(), stats.shown(), stats.files.len());
+        if let Err(e) = platform::show_notification("Hooligan cleaned up", text.as_str()) {
+            writeln!(self.log, "WARNING: failed to show notification: {e:?}");
+        }
+    }
+
+    /// Overwrite `last-run.json` with what this run did. Like the stats files it is informational only, so failures are
+    /// logged and otherwise ignored.
+    fn write_summary(&mut self, stats: &RunStats) {
+        let data_dir = self.project_dirs.data_local_dir();
+        let summary_path = data_dir.join("last-run.json");
+        if let Err(e) = fs::create_dir_all(data_dir).and_then(|()| fs::write(summary_path.as_path(), stats.serialize(current_timestamp()))) {
+            self.log.log(Level::Error, format_args!("error writing run summary {}: {e:?}", summary_path.display()));
+        }
+    }
+
+    /// Our own files living in the moderations directory (or vice versa) is almost certainly a setup mistake
+    fn warn_on_directory_overlap(&mut self, moderations_path: &Path) {
+        let own_dirs = [
+            ("data", self.project_dirs.data_local_dir().to_path_buf()),
+            ("config", self.project_dirs.config_local_dir().to_path_buf()),
+        ];
+        for (name, own_dir) in own_dirs {
+            if paths_overlap(moderations_path, own_dir.as_path()) {
+                writeln!(self.log, "WARNING: {name} directory {} overlaps moderations directory {}", own_dir.display(), moderations_path.display());
+            }
+        }
+    }
+
+    /// Open the log file using the limits from `config`, writing out any lines logged before now
+    fn open_log(&mut self, config: &Config) {
+        let log = logging::get_logger(&self.project_dirs, config.max_log_files, config.max_log_file_size_bytes)
+            .expect("failed to open log file for writing");
+        let held = mem::replace(&mut self.log, log);
+        self.log.append(held);
+    }
+
     /// Load config from disk
     fn load_config(&mut self) -> Config {
         let config_dir = self.project_dirs.config_local_dir();
         let config_path = config_dir.join("config.props");
         if config_path.is_file() {
             match Config::load(config_path.as_path()).map_err(Error::ConfigLoad) {
-                Ok(config) => config,
+                Ok(mut config) => {
+                    let version = config.version;
+                    if version > config::CURRENT_VERSION {
+                        writeln!(self.log, "WARNING: config is version {version}, but this version of hooligan only understands up to version {}. Settings it doesn't recognize are ignored.", config::CURRENT_VERSION);
+                    } else if config.migrate() {
+                        match config.serialize(config_path.as_path()) {
+                            Ok(()) => writeln!(self.log, "migrated config from version {version} to {}", config.version),
+                            Err(e) => self.log.log(Level::Error, format_args!("error saving migrated config: {e:?}")),
+                        }
+                    }
+                    config
+                }
                 Err(e) => {
-                    writeln!(self.log, "failed to load config and falling back to default: {e:?}");
+                    // move the broken config aside so the user's work isn't lost when a default config is written
+                    let bad_config_path = config_dir.join("config.props.bad");
+                    match fs::rename(config_path.as_path(), bad_config_path.as_path()) {
+                        Ok(()) => self.log.log(Level::Error, format_args!("failed to load config and falling back to default. It was moved to {}: {e:?}", bad_config_path.display())),
+                        Err(rename_error) => self.log.log(Level::Error, format_args!("failed to load config and falling back to default: {e:?}. Moving it aside also failed: {rename_error:?}")),
+                    }
                     Config::default()
                 }
             }
         } else {
-            let config = Config::default();
-            if let Err(e) = fs::create_dir_all(config_dir) {
-                writeln!(self.log, "error creating config directory: {e:?}");
+            let write = !config::write_default_suppressed();
+            if !write {
+                writeln!(self.log, "using default config without creating {} because {} is set", config_path.display(), config::NO_WRITE_CONFIG_VAR);
             }
-            if let Err(e) = config.serialize(config_path.as_path()) {
-                writeln!(self.log, "error saving default config: {e:?}");
+            Config::create_default(config_path.as_path(), write).unwrap_or_else(|e| {
+                self.log.log(Level::Error, format_args!("error saving default config: {e:?}"));
+                Config::default()
+            })
+        }
+    }
+
+    /// print every user at or above their threshold in each history file, either one per line or as a JSON array,
+    /// without modifying anything
+    fn report_status(&mut self, config: &Config, json: bool) -> Result<(), Error> {
+        let mut users = Vec::new();
+        for history_path in self.history_paths()? {
+            let name = history_path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let Some(counts) = self.read_history_for_report(history_path.as_path(), config)? else {
+                continue;
+            };
+            let stuck: BTreeMap<String, ShowHideCount> = counts.into_iter()
+                .filter(|(key, show_hide_count)| show_hide_count.count() >= transaction::threshold_for(config, key, Some(show_hide_count)))
+                .collect();
+            users.extend(stuck.into_iter().map(|(key, show_hide_count)| StuckUser {
+                file: name.clone(),
+                key,
+                show_hide_count,
+            }));
+        }
+        let report = if json {
+            StuckUser::to_json(&users)
+        } else {
+            StuckUser::to_human(&users)
+        };
+        io::stdout().write_all(report.as_bytes()).map_err(Error::Io)?;
+        Ok(())
+    }
+
+    /// print every transaction for one user in each history file that mentions them, followed by where that leaves
+    /// their show count, either one per line or as a JSON array, without modifying anything
+    fn report_history(&mut self, key: &str, config: &Config, json: bool) -> Result<(), Error> {
+        let mut histories = Vec::new();
+        let keys = HashSet::from([key.to_owned()]);
+        for history_path in self.history_paths()? {
+            let name = history_path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let history_file = File::open(history_path.as_path()).map_err(Error::Io)?;
+            let transactions = transaction::read_key_history(&history_file, key, config, |e| self.log.log(Level::Debug, format_args!("skipping unknown transaction {e:?}")))?;
+            if transactions.is_empty() {
+                continue;
+            }
+            let mut counts = transaction::read_log_for_keys(&history_file, &keys, config, current_timestamp(), |_| {})?;
+            histories.push(KeyHistory {
+                file: name,
+                transactions,
+                show_hide_count: counts.remove(key),
+            });
+        }
+        let report = if json {
+            KeyHistory::to_json(&histories)
+        } else {
+            KeyHistory::to_human(&histories)
+        };
+        io::stdout().write_all(report.as_bytes()).map_err(Error::Io)?;
+        writeln!(self.log, "found history for {key} in {} files", histories.len());
+        Ok(())
+    }
+
+    /// write every user's show count and state in each history file to `path` as CSV
+    fn export_csv(&mut self, path: &Path, config: &Config) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path).map_err(Error::Io)?);
+        writer.write_all(csv::record(["file", "user", "show_count", "state"]).as_bytes()).map_err(Error::Io)?;
+        let mut user_count: usize = 0;
+        for history_path in self.history_paths()? {
+            // history is named after its .vrcset file, which may not be valid unicode
+            let name = history_path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let Some(counts) = self.read_history_for_report(history_path.as_path(), config)? else {
+                continue;
+            };
+            let counts: BTreeMap<String, ShowHideCount> = counts.into_iter().collect();
+            for (key, show_hide_count) in &counts {
+                let record = csv::record([name.as_str(), key, &show_hide_count.count().to_string(), show_hide_count.state_name()]);
+                writer.write_all(record.as_bytes()).map_err(Error::Io)?;
             }
-            config
+            user_count += counts.len();
         }
+        writer.flush().map_err(Error::Io)?;
+        writeln!(self.log, "exported {user_count} show counts to {}", path.display());
+        Ok(())
+    }
+
+    /// write every user that is currently sticky in any history file to `path`, either one per line or as a JSON array
+    fn export_allowlist(&mut self, path: &Path, config: &Config, json: bool) -> Result<(), Error> {
+        let mut sticky_users: BTreeSet<String> = BTreeSet::new();
+        for history_path in self.history_paths()? {
+            let Some(counts) = self.read_history_for_report(history_path.as_path(), config)? else {
+                continue;
+            };
+            let sticky = counts.into_iter()
+                .filter(|(key, show_hide_count)| show_hide_count.is_sticky(transaction::threshold_for(config, key, Some(show_hide_count))))
+                .map(|(key, _)| key);
+            sticky_users.extend(sticky);
+        }
+
+        let mut writer = BufWriter::new(File::create(path).map_err(Error::Io)?);
+        if json {
+            writeln!(writer, "{}", json::string_array(&sticky_users)).map_err(Error::Io)?;
+        } else {
+            for key in &sticky_users {
+                writeln!(writer, "{key}").map_err(Error::Io)?;
+            }
+        }
+        writer.flush().map_err(Error::Io)?;
+        writeln!(self.log, "exported {} sticky users to {}", sticky_users.len(), path.display());
+        Ok(())
     }
 
-    /// process a *.vrcset file
-    fn process_file(&mut self, dir_entry: DirEntry, config: &Config) -> Result<(), Error> {
-        if dir_entry.file_name().as_encoded_bytes().ends_with(b".vrcset") {
-            let vrcset_path = dir_entry.path();
-            if vrcset_path.is_file() {
-                // calculate some paths and filenames
-                let mut transaction_log_path = self.project_dirs.data_local_dir().join("history");
-                fs::create_dir_all(transaction_log_path.as_path()).map_err(Error::Io)?;
-                let vrcset_os_filename = vrcset_path.file_name().unwrap();
-                let vrcset_filename = vrcset_os_filename.to_str().ok_or_else(|| Error::BadFilename(vrcset_os_filename.to_owned()))?;
-                let transaction_log_filename = vrcset_filename
-                    .split_once('.')
-                    .ok_or_else(|| Error::BadFilename(vrcset_os_filename.to_owned()))?
-                    .0.to_string() + ".history";
-                transaction_log_path.push(transaction_log_filename);
-
-                // read ordered transaction log counting shows since last hide into a map
-                let transaction_log_file = {
+    /// Record a manual reset for every user in every history file, so that nobody is sticky anymore. The .vrcset files
+    /// are left alone; the next normal run removes shown users from them as it would for anyone without history. With
+    /// `dry_run`, the resets are only logged.
+    fn reset_all(&mut self, config: &Config, dry_run: bool) -> Result<(), Error> {
+        let history_dir = self.history_dir();
+        if !history_dir.is_dir() {
+            return Ok(());
+        }
+        let now = current_timestamp();
+        for dir_entry in fs::read_dir(history_dir).map_err(Error::Io)? {
+            let history_path = dir_entry.map_err(Error::Io)?.path();
+            if history_path.extension().is_some_and(|extension| extension == "history") && history_path.is_file() {
+                let history_file = {
                     let mut open_options = OpenOptions::new();
                     open_options.read(true);
                     open_options.append(true);
-                    open_options.create(true);
-                    open_options.open(transaction_log_path.as_path()).map_err(Error::Io)?
+                    open_options.open(history_path.as_path()).map_err(Error::Io)?
                 };
-                let mut shows_since_last_hide = if transaction_log_path.is_file() {
-                    Some(transaction::read_log(&transaction_log_file)?)
+                let resets: Vec<Transaction> = transaction::read_log(&history_file, config, now, |e| self.log.log(Level::Debug, format_args!("skipping unknown transaction {e:?}")))?.into_iter()
+                    // auto reset users are back in the default state, but still have a show count to forget
+                    .filter(|(_, show_hide_count)| !show_hide_count.is_default() || show_hide_count.count() != 0)
+                    .map(|(key, _)| Transaction::new(key, TransactionValue::ManualReset, now))
+                    .collect();
+                if dry_run {
+                    writeln!(self.log, "dry run: would reset {} users in {}", resets.len(), history_path.display());
                 } else {
-                    None
-                };
-
-                // stream changes to vrcset file
-                let vrcset_file = {
-                    let mut open_options = OpenOptions::new();
-                    open_options.read(true);
-                    open_options.write(true);
-                    open_options.open(vrcset_path.as_path()).map_err(Error::Io)?
-                };
-                let mut removed: u32 = 0; // track removed lines
-                let mut retained: u32 = 0; // track retained lines that we would have normally removed, if not for the threshold
-                let mut pending_transactions: Vec<Transaction> = Vec::new(); // track difference between previous data and current data
-                let lines_to_remove = {
-                    let line_reader = BufReader::new(&vrcset_file).lines();
-                    line_reader.map(|maybe_line| { // parse the lines handling errors
-                        match maybe_line {
-                            Ok(line) => moderation::Line::parse(&line).map_err(Error::ShowHideParse),
-                            Err(e) => Err(Error::Io(e)),
-                        }
-                    })
-                }.filter(|line| {
-                    line.as_ref().map_or(true, |line| { // retain errors
-
-                        // number of times user was shown since last hide OR None if there is no data
-                        let shows = shows_since_last_hide.as_mut()
-                            .and_then(|map| map.remove(&line.key));
-
-                        match line.value {
-                            moderation::Value::Hide => { // we read a Hide from the vrcset file
-                                if shows.map(|shows| !shows.is_hidden()).unwrap_or(true) {
-                                    // if user was NOT last known to be hidden, record this manual hide
-                                    pending_transactions.push(Transaction::new(line.key.to_owned(), TransactionValue::ManualHide));
-                                }
-                                true // retain hidden user entries
-                            }
-                            moderation::Value::Show => { // we read a Show from the vrcset file
-                                // if we see a manual show in this block we need to consider it in the total show count
-                                let extra_shows = if shows.as_ref().map(|shows| !shows.is_shown()).unwrap_or(true) {
-                                    // if user was NOT last known to be shown, record this manual show
-                                    pending_transactions.push(Transaction::new(line.key.to_owned(), TransactionValue::ManualShow));
-                                    1
-                                } else {
-                                    0
-                                };
-
-                                // check if we've shown this user enough times that the show should stick
-                                if shows.map(|shows| shows.count() + extra_shows < config.auto_hide_threshold).unwrap_or(true) {
-                                    // not enough shows; reset the user
-                                    pending_transactions.push(Transaction::new(line.key.to_owned(), TransactionValue::AutoReset));
-                                    removed += 1;
-                                    false // remove entry
-                                } else {
-                                    // enough shows; retain the user
-                                    retained += 1;
-                                    true // retain entry
-                                }
-                            }
-                        }
-                    })
-                });
-                self.write_lines(&vrcset_file, lines_to_remove, true)?; // overwrite the vrcset file
-                writeln!(self.log, "removed {removed} and retained {retained} shown user entries from {vrcset_filename}");
-
-                // handle any remaining entries in the map
-                if let Some(shows_since_last_hide) = shows_since_last_hide {
-                    let mut shown: u32 = 0;
-
-                    let (default_lines, non_default_lines): (Vec<_>, Vec<_>) = shows_since_last_hide.into_iter()
-                        .partition(|(_, state)| state.is_default());
-
-                    // handle manual non-default -> default transitions
-                    non_default_lines.into_iter()
-                        .for_each(|(key, _)| pending_transactions.push(Transaction::new(key, TransactionValue::ManualReset)));
-
-                    // handle case where the show threshold has lowered: we need to go back and re-show previously reset users
-                    let mut lines_to_show = default_lines.into_iter()
-                        .filter(|(_, show_hide_count)| show_hide_count.count() >= config.auto_hide_threshold)
-                        .map(|(key, _)| {
-                            shown += 1;
-                            pending_transactions.push(Transaction::new(key.clone(), TransactionValue::AutoShow));
-                            Ok(moderation::Line::new(key, moderation::Value::Show))
-                        }).peekable();
-                    if lines_to_show.peek().is_some() {
-                        // reopen file in append mode and write these lines
-                        let mut open_options = OpenOptions::new();
-                        open_options.append(true);
-                        let vrcset_file = open_options.open(vrcset_path.as_path()).map_err(Error::Io)?;
-                        self.write_lines(&vrcset_file, lines_to_show, false)?;
-                        writeln!(self.log, "added {shown} shown user entries to {vrcset_filename}");
-                    }
+                    writeln!(self.log, "resetting {} users in {}", resets.len(), history_path.display());
+                    transaction::write_run(&history_file, now, resets)?;
                 }
+            }
+        }
+        Ok(())
+    }
 
-                // persist changes to transaction log
-                writeln!(self.log, "about to record {} transactions", pending_transactions.len());
-                transaction::write_log(&transaction_log_file, pending_transactions)?;
+    /// print what changed in a .vrcset file since hooligan last recorded it, without modifying anything
+    fn report_external_changes(&mut self, moderations_path: &Path, name: &str, config: &Config, json: bool) -> Result<(), Error> {
+        let named = NamedVrcset::parse(name);
+        let transaction_log_path = named.history_path(self.history_dir().as_path())
+            .ok_or_else(|| Error::BadFilename(OsString::from(name)))?;
+        let vrcset_file = File::open(named.vrcset_path(moderations_path)).map_err(Error::Io)?;
+        let mut lines = Vec::new();
+        for line in BufReader::new(vrcset_file).lines() {
+            match moderation::Line::parse_non_blank(&line.map_err(Error::Io)?) {
+                Some(Ok(line)) => lines.push(line),
+                Some(Err(e)) => writeln!(self.log, "omitting line due to parse error {e:?}"),
+                None => {}
             }
         }
 
+        let now = current_timestamp();
+        let history = match File::open(transaction_log_path.as_path()) {
+            Ok(file) => match transaction::read_log(&file, config, now, |e| self.log.log(Level::Debug, format_args!("skipping unknown transaction {e:?}"))) {
+                Err(Error::HistoryTooLarge) => {
+                    // as in a normal run, only the users in the vrcset file can be tracked, which leaves out anyone cleared from it
+                    writeln!(self.log, "WARNING: {} has more than {} users; only tracking users present in {}, so cleared users can't be reported. Consider compacting your history.",
+                             transaction_log_path.display(), config.max_history_keys, named.filename);
+                    let keys: HashSet<String> = lines.iter().map(|line| line.key.clone()).collect();
+                    transaction::read_log_for_keys(&file, &keys, config, now, |_| {})?
+                }
+                history => history?,
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        let changes = ExternalChanges::compute(lines, history);
+        let report = if json {
+            changes.to_json()
+        } else {
+            changes.to_human()
+        };
+        writeln!(self.log, "external changes to {name}:\n{report}");
+        io::stdout().write_all(report.as_bytes()).map_err(Error::Io)?;
         Ok(())
     }
 
-    fn write_lines<T: Iterator<Item=Result<moderation::Line, Error>>>(&mut self, file: &File, line_iter: T, truncate: bool) -> Result<(), Error> {
-        let mut writer = BufWriter::new(file);
-        let mut size: u64 = 0;
-        for line in line_iter {
-            match line {
-                Ok(line) => {
-                    let serialized = line.serialize();
-                    size += u64::try_from(writer.write(serialized.as_bytes()).map_err(Error::Io)?).map_err(Error::U64FromInt)?;
-                }
-                Err(Error::ShowHideParse(e)) => {
-                    writeln!(self.log, "omitting line due to parse error {e:?}");
-                }
-                Err(e) => {
-                    // We got some kind of IO Error (or an unexpected error type got passed in)
-                    // This is awful and has a high chance of file corruption, but the panic might save us of the BufWriter hasn't flushed yet
-                    writeln!(self.log, "error {e:?} while streaming file modifications; I will now panic");
-                    panic!("error {e:?} while streaming file modifications");
-                }
+    /// print log lines mentioning a .vrcset file interleaved with its history, in chronological order
+    fn report_timeline(&mut self, name: &str, json: bool) -> Result<(), Error> {
+        let named = NamedVrcset::parse(name);
+        let display_name = named.display_name();
+        let mut timeline = Timeline::default();
+
+        // make sure this run's log lines so far are included
+        self.log.flush().map_err(Error::Io)?;
+        for log_path in logging::get_log_files(&self.project_dirs).map_err(Error::Io)? {
+            for line in logging::read_log_file(log_path.as_path()).map_err(Error::Io)?.lines() {
+                timeline.add_log_line(&line.map_err(Error::Io)?, &display_name);
             }
         }
-        writer.flush().map_err(Error::Io)?;
-        if truncate {
-            file.set_len(size).map_err(Error::Io)?;
+
+        let transaction_log_path = named.history_path(self.history_dir().as_path())
+            .ok_or_else(|| Error::BadFilename(OsString::from(name)))?;
+        match File::open(transaction_log_path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line.map_err(Error::Io)?;
+                    if transaction::is_run_marker(&line) || transaction::is_checkpoint(&line) {
+                        continue;
+                    }
+                    match Transaction::parse(&line) {
+                        Ok(transaction) => timeline.add_transaction(&transaction),
+                        Err(e) => writeln!(self.log, "omitting transaction due to parse error {e:?}"),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::Io(e)),
         }
+
+        let events = timeline.into_events();
+        let report = if json {
+            Timeline::to_json(&events)
+        } else {
+            Timeline::to_human(&events)
+        };
+        io::stdout().write_all(report.as_bytes()).map_err(Error::Io)?;
         Ok(())
     }
 
-    /// launch the provided process
-    fn spawn_process(&mut self) -> Result<(), Error> {
-        let mut args = env::args().skip(1); // we skip the first arg because it's just a path to this executable
-        if let Some(command) = args.next() {
-            // we got args, blindly run them as a command
+    /// Count shows in a history file for a report, or `None` if it has more than `max_history_keys` users. A report
+    /// covers every user in the file, so unlike a normal run there's no smaller set of users to fall back on; the file
+    /// is left out with a warning instead of failing the whole report.
+    fn read_history_for_report(&mut self, history_path: &Path, config: &Config) -> Result<Option<HashMap<String, ShowHideCount>>, Error> {
+        let history_file = File::open(history_path).map_err(Error::Io)?;
+        match transaction::read_log(&history_file, config, current_timestamp(), |e| self.log.log(Level::Debug, format_args!("skipping unknown transaction {e:?}"))) {
+            Err(Error::HistoryTooLarge) => {
+                writeln!(self.log, "WARNING: {} has more than {} users; leaving it out. Consider compacting your history.",
+                         history_path.display(), config.max_history_keys);
+                Ok(None)
+            }
+            counts => counts.map(Some),
+        }
+    }
+
+    fn history_dir(&self) -> PathBuf {
+        self.project_dirs.data_local_dir().join("history")
+    }
+
+    /// every history file, sorted by path
+    fn history_paths(&self) -> Result<Vec<PathBuf>, Error> {
+        let history_dir = self.history_dir();
+        let mut history_paths = Vec::new();
+        if history_dir.is_dir() {
+            for dir_entry in fs::read_dir(history_dir).map_err(Error::Io)? {
+                let history_path = dir_entry.map_err(Error::Io)?.path();
+                if history_path.extension().is_some_and(|extension| extension == "history") && history_path.is_file() {
+                    history_paths.push(history_path);
+                }
+            }
+        }
+        history_paths.sort_unstable();
+        Ok(history_paths)
+    }
+
+    /// launch the provided process, or `launch_command` from the config if no process was provided, retrying up to
+    /// `launch_retries` times if it fails to start. Returns the child if one was launched.
+    fn spawn_process(&mut self, args: &Args, config: &Config) -> Result<Option<Child>, Error> {
+        if args.no_launch {
+            writeln!(self.log, "not launching due to --no-launch");
+            return Ok(None);
+        }
+        let command_line: Vec<OsString> = if args.command.is_empty() {
+            let command_line = config.launch_command.as_deref().map(config::split_command).unwrap_or_default();
+            if !command_line.is_empty() {
+                writeln!(self.log, "using launch_command from config");
+            }
+            command_line.into_iter().map(OsString::from).collect()
+        } else {
+            writeln!(self.log, "using command from args");
+            args.command.clone()
+        };
+        if let Some((command, args)) = command_line.split_first() {
+            // we got a command, blindly run it
             let mut command = Command::new(command);
             command.args(args);
             writeln!(self.log, "spawning {command:?}");
-            let _ = command.spawn().map_err(Error::Io)?;
+            let mut retries: u32 = 0;
+            let child = loop {
+                match command.spawn() {
+                    Ok(child) => break child,
+                    Err(e) if retries < config.launch_retries => {
+                        retries += 1;
+                        let delay = LAUNCH_RETRY_DELAY * retries;
+                        writeln!(self.log, "failed to spawn {command:?}: {e}; retry {retries} of {} in {delay:?}", config.launch_retries);
+                        thread::sleep(delay);
+                    }
+                    Err(e) => {
+                        self.log.log(Level::Error, format_args!("giving up on spawning {command:?} after {} attempts", retries + 1));
+                        return Err(Error::Io(e));
+                    }
+                }
+            };
+            if retries != 0 {
+                writeln!(self.log, "spawned {command:?} after {retries} retries");
+            }
+            return Ok(Some(child));
         }
-        Ok(())
+        Ok(None)
     }
 }
 
-/// calculate the path to %UserProfile%\AppData\LocalLow\VRChat\VRChat\LocalPlayerModerations
+/// calculate the path to %UserProfile%\AppData\LocalLow\VRChat\VRChat\LocalPlayerModerations, unless
+/// `HOOLIGAN_MODERATIONS_DIR` is set, in which case it is used as-is
 fn get_local_player_moderations_path() -> Result<PathBuf, Error> {
+    if let Some(moderations_dir) = env::var_os(MODERATIONS_DIR_VAR).filter(|moderations_dir| !moderations_dir.is_empty()) {
+        return Ok(PathBuf::from(moderations_dir));
+    }
     let user_profile_path = env::var("UserProfile").map_err(Error::EnvironmentVar)?;
     let mut local_player_moderations_path = PathBuf::from(user_profile_path);
     local_player_moderations_path.push("AppData");
@@ -287,8 +678,43 @@ fn get_local_player_moderations_path() -> Result<PathBuf, Error> {
     Ok(local_player_moderations_path)
 }
 
-fn get_project_dirs() -> Result<ProjectDirs, io::Error> {
-    let project_dirs = ProjectDirs::from("zkxs.dev", "", "hooligan")
+/// Skipped cleanups get an exit code of their own, so scripts can tell them apart. Other errors are logged, but still
+/// exit successfully.
+fn exit_code(e: &Error) -> ExitCode {
+    match e {
+        Error::VrchatRunning => ExitCode::from(VRCHAT_RUNNING_EXIT_CODE),
+        Error::Lock(lock::Error::WouldBlock) => ExitCode::from(LOCK_TIMEOUT_EXIT_CODE),
+        _ => ExitCode::SUCCESS,
+    }
+}
+
+/// true if either path is inside the other
+fn paths_overlap(a: &Path, b: &Path) -> bool {
+    let a = fs::canonicalize(a).unwrap_or_else(|_| a.to_path_buf());
+    let b = fs::canonicalize(b).unwrap_or_else(|_| b.to_path_buf());
+    a.starts_with(b.as_path()) || b.starts_with(a.as_path())
+}
+
+/// `profile` gets its own separate config, data, and log directories
+fn get_project_dirs(profile: Option<&str>) -> Result<ProjectDirs, io::Error> {
+    let application = match profile {
+        Some(profile) => format!("hooligan-{profile}"),
+        None => "hooligan".to_owned(),
+    };
+    let project_dirs = ProjectDirs::from("zkxs.dev", "", &application)
         .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "failed to find valid project directory"))?;
     Ok(project_dirs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(&Error::VrchatRunning), ExitCode::from(VRCHAT_RUNNING_EXIT_CODE));
+        assert_eq!(exit_code(&Error::Lock(lock::Error::WouldBlock)), ExitCode::from(LOCK_TIMEOUT_EXIT_CODE));
+        assert_eq!(exit_code(&Error::Lock(lock::Error::Io(io::Error::other("disk on fire")))), ExitCode::SUCCESS);
+        assert_eq!(exit_code(&Error::HistoryChecksum(1)), ExitCode::SUCCESS);
+    }
+}
@@ -0,0 +1,148 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Platform-specific process detection, used to avoid fighting a running VRChat over its .vrcset files, and desktop
+//! notifications
+
+use std::{fs, io};
+use std::path::Path;
+
+/// image name of the VRChat client process. Under Proton the Wine process carries the same name.
+pub const VRCHAT_PROCESS_NAME: &str = "VRChat.exe";
+
+/// Something that can tell whether a process is running. Tests substitute a stub for [`System`].
+pub trait Processes {
+    /// true if any running process has the image name `name`, ignoring ASCII case
+    fn is_running(&self, name: &str) -> io::Result<bool>;
+}
+
+/// the processes actually running on this machine
+pub struct System;
+
+impl Processes for System {
+    #[cfg(windows)]
+    fn is_running(&self, name: &str) -> io::Result<bool> {
+        use std::os::windows::process::CommandExt;
+        use std::process::Command;
+
+        /// keeps tasklist from flashing a console window, as hooligan itself has none
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+        let output = Command::new("tasklist")
+            .args(["/FO", "CSV", "/NH", "/FI"])
+            .arg(format!("IMAGENAME eq {name}"))
+            .creation_flags(CREATE_NO_WINDOW)
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("tasklist exited with {}", output.status)));
+        }
+        Ok(tasklist_contains(String::from_utf8_lossy(&output.stdout).as_ref(), name))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn is_running(&self, name: &str) -> io::Result<bool> {
+        proc_contains(Path::new("/proc"), name)
+    }
+
+    #[cfg(not(any(windows, target_os = "linux")))]
+    fn is_running(&self, _name: &str) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// true if VRChat is running, and so may be writing its .vrcset files at any moment
+pub fn vrchat_running<P: Processes>(processes: &P) -> io::Result<bool> {
+    processes.is_running(VRCHAT_PROCESS_NAME)
+}
+
+/// Show a desktop notification. This is a no-op everywhere but Windows.
+#[cfg(windows)]
+pub fn show_notification(title: &str, text: &str) -> io::Result<()> {
+    use tauri_winrt_notification::Toast;
+
+    Toast::new(Toast::POWERSHELL_APP_ID)
+        .title(title)
+        .text1(text)
+        .show()
+        .map_err(|e| io::Error::other(format!("{e:?}")))
+}
+
+/// Show a desktop notification. This is a no-op everywhere but Windows.
+#[cfg(not(windows))]
+pub fn show_notification(_title: &str, _text: &str) -> io::Result<()> {
+    Ok(())
+}
+
+/// Check `tasklist /FO CSV /NH` output for a process. The first CSV field of each line is the image name; when nothing
+/// matches the filter tasklist prints an informational message instead.
+#[cfg_attr(not(any(windows, test)), allow(dead_code))]
+fn tasklist_contains(output: &str, name: &str) -> bool {
+    output.lines()
+        .filter_map(|line| line.trim().strip_prefix('"'))
+        .filter_map(|line| line.split_once('"'))
+        .any(|(image_name, _)| image_name.eq_ignore_ascii_case(name))
+}
+
+/// Check the `comm` of every process in a procfs mount for a process. Processes that exit while we're looking are
+/// skipped.
+#[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+fn proc_contains(proc_dir: &Path, name: &str) -> io::Result<bool> {
+    for dir_entry in fs::read_dir(proc_dir)? {
+        let dir_entry = dir_entry?;
+        if !dir_entry.file_name().as_encoded_bytes().iter().all(u8::is_ascii_digit) {
+            continue;
+        }
+        match fs::read_to_string(dir_entry.path().join("comm")) {
+            Ok(comm) if comm.trim_end().eq_ignore_ascii_case(name) => return Ok(true),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    struct Stub(&'static [&'static str]);
+
+    impl Processes for Stub {
+        fn is_running(&self, name: &str) -> io::Result<bool> {
+            Ok(self.0.iter().any(|running| running.eq_ignore_ascii_case(name)))
+        }
+    }
+
+    #[test]
+    fn test_vrchat_running() {
+        assert!(vrchat_running(&Stub(&["explorer.exe", "vrchat.exe"])).unwrap());
+        assert!(!vrchat_running(&Stub(&["explorer.exe", "VRChatLauncher.exe"])).unwrap());
+        assert!(!vrchat_running(&Stub(&[])).unwrap());
+    }
+
+    #[test]
+    fn test_tasklist_contains() {
+        let output = "\r\n\"VRChat.exe\",\"1234\",\"Console\",\"1\",\"1,234,567 K\"\r\n";
+        assert!(tasklist_contains(output, VRCHAT_PROCESS_NAME));
+        assert!(!tasklist_contains(output, "hooligan.exe"));
+        assert!(!tasklist_contains("INFO: No tasks are running which match the specified criteria.\r\n", VRCHAT_PROCESS_NAME));
+    }
+
+    #[test]
+    fn test_proc_contains() {
+        let proc_dir = TestDir::new("platform-proc");
+        for (pid, comm) in [("1", "systemd\n"), ("42", "VRChat.exe\n")] {
+            fs::create_dir(proc_dir.join(pid)).unwrap();
+            fs::write(proc_dir.join(pid).join("comm"), comm).unwrap();
+        }
+        // not a process, and has no comm
+        fs::create_dir(proc_dir.join("self-test")).unwrap();
+        // a process that exited between listing and reading
+        fs::create_dir(proc_dir.join("7")).unwrap();
+        assert!(proc_contains(proc_dir.as_path(), VRCHAT_PROCESS_NAME).unwrap());
+        assert!(!proc_contains(proc_dir.as_path(), "hooligan.exe").unwrap());
+    }
+}
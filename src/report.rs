@@ -0,0 +1,150 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! The output of the read-only `status` and `history` commands, either for people or, with `--json`, for scripts
+
+use crate::json;
+use crate::transaction::{ShowHideCount, Transaction};
+
+/// a user at or above their threshold in one history file
+#[derive(Debug, PartialEq, Eq)]
+pub struct StuckUser {
+    /// the history file's name, without its extension
+    pub file: String,
+    pub key: String,
+    pub show_hide_count: ShowHideCount,
+}
+
+impl StuckUser {
+    /// one `<file>: <key> <state> <count>` line per user, followed by ` since <timestamp>` if the first show was recorded
+    pub fn to_human(users: &[Self]) -> String {
+        let mut human = String::new();
+        for user in users {
+            human.push_str(&format!("{}: {} {} {}", user.file, user.key, user.show_hide_count.state_name(), user.show_hide_count.count()));
+            if let Some(first_show_timestamp) = user.show_hide_count.first_show_timestamp() {
+                human.push_str(&format!(" since {first_show_timestamp}"));
+            }
+            human.push('\n');
+        }
+        human
+    }
+
+    pub fn to_json(users: &[Self]) -> String {
+        let users: Vec<String> = users.iter()
+            .map(|user| format!("{{\"file\":{},\"user\":{},\"state\":{},\"count\":{},\"since\":{}}}",
+                                json::string(&user.file),
+                                json::string(&user.key),
+                                json::string(user.show_hide_count.state_name()),
+                                user.show_hide_count.count(),
+                                json_number(user.show_hide_count.first_show_timestamp())))
+            .collect();
+        format!("[{}]\n", users.join(","))
+    }
+}
+
+/// every transaction for one user in one history file, and where that leaves them
+pub struct KeyHistory {
+    /// the history file's name, without its extension
+    pub file: String,
+    /// oldest first
+    pub transactions: Vec<Transaction>,
+    /// `None` if the history has no show count for the user
+    pub show_hide_count: Option<ShowHideCount>,
+}
+
+impl KeyHistory {
+    /// one `<file>: <transaction>` line per transaction, followed by ` at <timestamp>` if it was recorded, then a
+    /// `<file>: now <state> <count>` line for each file
+    pub fn to_human(histories: &[Self]) -> String {
+        let mut human = String::new();
+        for history in histories {
+            for transaction in &history.transactions {
+                human.push_str(&format!("{}: {}", history.file, transaction.value.serialize()));
+                if let Some(timestamp) = transaction.timestamp {
+                    human.push_str(&format!(" at {timestamp}"));
+                }
+                human.push('\n');
+            }
+            if let Some(show_hide_count) = &history.show_hide_count {
+                human.push_str(&format!("{}: now {} {}\n", history.file, show_hide_count.state_name(), show_hide_count.count()));
+            }
+        }
+        human
+    }
+
+    pub fn to_json(histories: &[Self]) -> String {
+        let histories: Vec<String> = histories.iter()
+            .map(|history| {
+                let transactions: Vec<String> = history.transactions.iter()
+                    .map(|transaction| format!("{{\"value\":{},\"timestamp\":{}}}",
+                                               json::string(transaction.value.serialize()),
+                                               json_number(transaction.timestamp)))
+                    .collect();
+                let now = match &history.show_hide_count {
+                    Some(show_hide_count) => format!("{{\"state\":{},\"count\":{}}}",
+                                                     json::string(show_hide_count.state_name()),
+                                                     show_hide_count.count()),
+                    None => "null".to_owned(),
+                };
+                format!("{{\"file\":{},\"transactions\":[{}],\"now\":{now}}}", json::string(&history.file), transactions.join(","))
+            })
+            .collect();
+        format!("[{}]\n", histories.join(","))
+    }
+}
+
+/// `value` as a JSON number, or `null` if there isn't one
+fn json_number(value: Option<u64>) -> String {
+    value.map_or_else(|| "null".to_owned(), |value| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_util::TestFile;
+    use crate::transaction;
+
+    #[test]
+    fn test_stuck_users() {
+        let file = TestFile::new("report-stuck-users", "usr_a MANUAL_SHOW 100\nusr_b MANUAL_SHOW\n");
+        let mut history = transaction::read_log(&file, &Config::default(), 0, |_| {}).unwrap();
+        let users = vec![
+            StuckUser {
+                file: "a".to_owned(),
+                key: "usr_a".to_owned(),
+                show_hide_count: history.remove("usr_a").unwrap(),
+            },
+            StuckUser {
+                file: "b \"c\"".to_owned(),
+                key: "usr_b".to_owned(),
+                show_hide_count: history.remove("usr_b").unwrap(),
+            },
+        ];
+        assert_eq!(StuckUser::to_human(&users), "a: usr_a shown 1 since 100\nb \"c\": usr_b shown 1\n");
+        assert_eq!(StuckUser::to_json(&users), "[{\"file\":\"a\",\"user\":\"usr_a\",\"state\":\"shown\",\"count\":1,\"since\":100},{\"file\":\"b \\\"c\\\"\",\"user\":\"usr_b\",\"state\":\"shown\",\"count\":1,\"since\":null}]\n");
+        assert_eq!(StuckUser::to_json(&[]), "[]\n");
+    }
+    #[test]
+    fn test_key_history() {
+        let file = TestFile::new("report-key-history", "usr_a MANUAL_SHOW 100\nusr_a AUTO_RESET\nusr_b MANUAL_SHOW\n");
+        let config = Config::default();
+        let transactions = transaction::read_key_history(&file, "usr_a", &config, |_| {}).unwrap();
+        let mut counts = transaction::read_log(&file, &config, 0, |_| {}).unwrap();
+        let histories = vec![
+            KeyHistory {
+                file: "a".to_owned(),
+                transactions,
+                show_hide_count: counts.remove("usr_a"),
+            },
+            KeyHistory {
+                file: "b".to_owned(),
+                transactions: vec![Transaction::new("usr_a".to_owned(), transaction::Value::ManualHide, 200)],
+                show_hide_count: None,
+            },
+        ];
+        assert_eq!(KeyHistory::to_human(&histories), "a: MANUAL_SHOW at 100\na: AUTO_RESET\na: now default 1\nb: MANUAL_HIDE at 200\n");
+        assert_eq!(KeyHistory::to_json(&histories), "[{\"file\":\"a\",\"transactions\":[{\"value\":\"MANUAL_SHOW\",\"timestamp\":100},{\"value\":\"AUTO_RESET\",\"timestamp\":null}],\"now\":{\"state\":\"default\",\"count\":1}},{\"file\":\"b\",\"transactions\":[{\"value\":\"MANUAL_HIDE\",\"timestamp\":200}],\"now\":null}]\n");
+    }
+}
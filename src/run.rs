@@ -0,0 +1,1761 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Processing every .vrcset file in a moderations directory
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, DirEntry, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Instant;
+
+use crate::config::Config;
+use crate::local_player_moderations as moderation;
+use crate::local_player_moderations::LineEnding;
+use crate::logging::{Level, LogFile};
+use crate::stats::FileStats;
+use crate::transaction::{self, ShowHideCount, Transaction, Undo, Value as TransactionValue};
+use crate::{atomic, current_timestamp, json, Error};
+
+/// account folders inside the moderations directory are named for the account's user ID
+const ACCOUNT_DIR_PREFIX: &str = "usr_";
+
+/// lines too long to parse are moved to a file with this tacked onto the .vrcset filename
+const QUARANTINE_EXTENSION: &str = ".quarantine";
+
+/// Options for a run that don't come from the config file
+#[derive(Debug, Default)]
+pub struct Options {
+    /// log what would change, but leave the .vrcset files and history untouched
+    pub dry_run: bool,
+    /// when run from a terminal, ask before modifying each file
+    pub confirm: bool,
+    /// collect which users were removed, retained, and shown in each file, not just how many
+    pub diff: bool,
+}
+
+/// The users behind the counts in [`FileRunStats`], only collected with [`Options::diff`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    pub removed: Vec<String>,
+    pub retained: Vec<String>,
+    pub shown: Vec<String>,
+}
+
+/// What one run did to a single .vrcset file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileRunStats {
+    /// shown users removed for not having been shown enough times
+    pub removed: u32,
+    /// shown users kept for having been shown enough times
+    pub retained: u32,
+    /// inactive hidden users removed due to `prune_hides_after_days`
+    pub pruned: u32,
+    /// previously removed users shown again after their threshold was lowered
+    pub shown: u32,
+    /// transactions recorded to the file's history
+    pub transactions: u32,
+    /// which users were removed, retained, and shown, if [`Options::diff`] was set
+    pub diff: FileDiff,
+}
+
+impl FileRunStats {
+    fn serialize(&self) -> String {
+        format!("{{\"removed\":{},\"retained\":{},\"pruned\":{},\"shown\":{},\"transactions\":{}}}",
+                self.removed, self.retained, self.pruned, self.shown, self.transactions)
+    }
+}
+
+/// What one run did to every .vrcset file it processed. Dry runs and declined changes are counted as if they had been
+/// applied.
+#[derive(Debug, Default)]
+pub struct RunStats {
+    /// counts for each processed file, by filename
+    pub files: BTreeMap<String, FileRunStats>,
+}
+
+impl RunStats {
+    pub fn removed(&self) -> u32 {
+        self.files.values().map(|file| file.removed).sum()
+    }
+
+    pub fn retained(&self) -> u32 {
+        self.files.values().map(|file| file.retained).sum()
+    }
+
+    pub fn pruned(&self) -> u32 {
+        self.files.values().map(|file| file.pruned).sum()
+    }
+
+    pub fn shown(&self) -> u32 {
+        self.files.values().map(|file| file.shown).sum()
+    }
+
+    pub fn transactions(&self) -> u32 {
+        self.files.values().map(|file| file.transactions).sum()
+    }
+
+    /// Serialize as a JSON object with the counts for each file and their totals
+    pub fn serialize(&self, timestamp: u64) -> String {
+        let files: Vec<String> = self.files.iter()
+            .map(|(filename, file)| format!("{}:{}", json::string(filename), file.serialize()))
+            .collect();
+        let total = FileRunStats {
+            removed: self.removed(),
+            retained: self.retained(),
+            pruned: self.pruned(),
+            shown: self.shown(),
+            transactions: self.transactions(),
+            diff: FileDiff::default(),
+        };
+        format!("{{\"timestamp\":{timestamp},\"files\":{{{}}},\"total\":{}}}\n", files.join(","), total.serialize())
+    }
+
+    /// Every file's [`FileDiff`] under a heading with its name, with one user per line marked `-` for removed, `=` for
+    /// retained, or `+` for shown. Files without changes are left out.
+    pub fn diff_report(&self) -> String {
+        let mut report = String::new();
+        for (filename, file) in &self.files {
+            let diff = &file.diff;
+            if diff.removed.is_empty() && diff.retained.is_empty() && diff.shown.is_empty() {
+                continue;
+            }
+            report.push_str(&format!("{filename}:\n"));
+            for (mark, keys) in [('-', &diff.removed), ('=', &diff.retained), ('+', &diff.shown)] {
+                for key in keys {
+                    report.push_str(&format!("  {mark} {key}\n"));
+                }
+            }
+        }
+        report
+    }
+}
+
+/// A directory of .vrcset files: the moderations directory itself, or the folder for one VRChat account inside it
+#[derive(Debug, PartialEq, Eq)]
+pub struct ModerationsDir {
+    /// name of the account folder, or `None` for the moderations directory itself
+    pub account: Option<String>,
+    pub path: PathBuf,
+}
+
+impl ModerationsDir {
+    /// how a file in this directory is named in logs and stats
+    fn display_name(&self, filename: &str) -> String {
+        display_name(self.account.as_deref(), filename)
+    }
+}
+
+/// `<ACCOUNT>/<FILENAME>` for a file in an account folder, or just the filename for one in the moderations directory
+fn display_name(account: Option<&str>, filename: &str) -> String {
+    match account {
+        Some(account) => format!("{account}/{filename}"),
+        None => filename.to_owned(),
+    }
+}
+
+/// `moderations_dir` followed by each account folder inside it, in name order
+pub fn moderations_dirs(moderations_dir: &Path) -> Result<Vec<ModerationsDir>, Error> {
+    let mut account_dirs = Vec::new();
+    for dir_entry in fs::read_dir(moderations_dir).map_err(Error::Io)? {
+        let dir_entry = dir_entry.map_err(Error::Io)?;
+        if let Ok(account) = dir_entry.file_name().into_string() {
+            if account.starts_with(ACCOUNT_DIR_PREFIX) && dir_entry.path().is_dir() {
+                account_dirs.push(ModerationsDir {
+                    account: Some(account),
+                    path: dir_entry.path(),
+                });
+            }
+        }
+    }
+    account_dirs.sort_unstable_by(|a, b| a.account.cmp(&b.account));
+    let mut dirs = vec![ModerationsDir {
+        account: None,
+        path: moderations_dir.to_path_buf(),
+    }];
+    dirs.extend(account_dirs);
+    Ok(dirs)
+}
+
+/// Process every .vrcset file in `moderations_dir` and its account folders that the config includes, keeping history
+/// in `history_dir`. A file that fails is logged and left out of the stats, and the rest are processed anyway. The
+/// caller is responsible for holding the directory lock.
+pub fn run_all(config: &Config, moderations_dir: &Path, history_dir: &Path, options: &Options, log: &mut LogFile) -> Result<RunStats, Error> {
+    let now = current_timestamp();
+    let mut run = Run {
+        log,
+        config,
+        history_dir,
+        options,
+        now,
+    };
+    let moderations_dirs = moderations_dirs(moderations_dir)?;
+
+    // clean up after any run that crashed partway through replacing a file
+    if options.dry_run {
+        writeln!(run.log, "dry run: no files will be modified");
+    } else {
+        let mut recoveries = Vec::new();
+        for dir in &moderations_dirs {
+            recoveries.push(atomic::recover(dir.path.as_path(), ".vrcset")?);
+        }
+        if history_dir.is_dir() {
+            recoveries.push(atomic::recover(history_dir, ".history")?);
+            recoveries.push(atomic::recover(history_dir, ".state.json")?);
+        }
+        for recovery in recoveries {
+            for path in recovery.discarded {
+                writeln!(run.log, "discarded incomplete write {}", path.display());
+            }
+            for path in recovery.restored {
+                writeln!(run.log, "restored missing {} from backup", path.display());
+            }
+        }
+    }
+
+    // iterate over all *.vrcset files
+    let mut dir_entries = Vec::new();
+    for dir in &moderations_dirs {
+        for dir_entry in fs::read_dir(dir.path.as_path()).map_err(Error::Io)? {
+            let dir_entry = dir_entry.map_err(Error::Io)?;
+            let filename = dir_entry.file_name().to_string_lossy().into_owned();
+            if config.includes_file(&filename) {
+                dir_entries.push((dir.display_name(&filename), dir.account.as_deref(), dir_entry));
+            } else {
+                run.log.log(Level::Debug, format_args!("skipping {} due to include_glob/exclude_glob", dir.display_name(&filename)));
+            }
+        }
+    }
+
+    let start = Instant::now();
+    let file_count = dir_entries.len();
+    let mut stats = RunStats::default();
+    if options.confirm {
+        // prompts share stdin, so they have to happen one file at a time
+        for (filename, account, dir_entry) in dir_entries {
+            match run.process_file(dir_entry, account) {
+                Ok(Some(file_stats)) => {
+                    stats.files.insert(filename, file_stats);
+                }
+                Ok(None) => {}
+                Err(e) => run.log.log(Level::Error, format_args!("error processing {filename}, moving on to the next file: {e:?}")),
+            }
+        }
+    } else {
+        // each file gets its own thread and its own log, and the logs are written out in directory order once every
+        // thread is done so that lines from different files don't interleave
+        let results = thread::scope(|scope| {
+            let handles: Vec<_> = dir_entries.into_iter()
+                .map(|(filename, account, dir_entry)| {
+                    let mut file_log = run.log.deferred();
+                    let handle = scope.spawn(move || {
+                        let mut file_run = Run {
+                            log: &mut file_log,
+                            config,
+                            history_dir,
+                            options,
+                            now,
+                        };
+                        let result = file_run.process_file(dir_entry, account);
+                        (file_log, result)
+                    });
+                    (filename, handle)
+                })
+                .collect();
+            handles.into_iter()
+                .map(|(filename, handle)| (filename, handle.join().expect("file processing thread panicked")))
+                .collect::<Vec<_>>()
+        });
+
+        for (filename, (file_log, result)) in results {
+            run.log.append(file_log);
+            match result {
+                Ok(Some(file_stats)) => {
+                    stats.files.insert(filename, file_stats);
+                }
+                Ok(None) => {}
+                Err(e) => run.log.log(Level::Error, format_args!("error processing {filename}, moving on to the next file: {e:?}")),
+            }
+        }
+    }
+    writeln!(run.log, "processing {file_count} files took {:?}", start.elapsed());
+    Ok(stats)
+}
+
+/// Reverse the most recent run recorded in the history of each .vrcset file that include_glob and exclude_glob allow,
+/// returning the number of .vrcset files that were changed
+pub fn undo_all(config: &Config, moderations_dir: &Path, history_dir: &Path, log: &mut LogFile) -> Result<u32, Error> {
+    let options = Options::default();
+    let mut run = Run {
+        log,
+        config,
+        history_dir,
+        options: &options,
+        now: current_timestamp(),
+    };
+    let mut undone: u32 = 0;
+    for dir in moderations_dirs(moderations_dir)? {
+        for dir_entry in fs::read_dir(dir.path.as_path()).map_err(Error::Io)? {
+            let vrcset_path = dir_entry.map_err(Error::Io)?.path();
+            let is_vrcset = vrcset_path.extension().is_some_and(|extension| extension == "vrcset") && vrcset_path.is_file();
+            let included = vrcset_path.file_name().is_some_and(|filename| config.includes_file(&filename.to_string_lossy()));
+            if is_vrcset && included && run.undo_file(vrcset_path.as_path(), dir.account.as_deref())? {
+                undone += 1;
+            }
+        }
+    }
+    Ok(undone)
+}
+
+/// What importing a hide list did across every .vrcset file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ImportStats {
+    /// users newly hidden, either by a new hide line or by replacing their show line
+    pub added: u32,
+    /// users skipped because their file already hid them
+    pub present: u32,
+}
+
+/// Read a hide list of one user per line, ignoring blank lines and keeping only the first of any duplicates
+pub fn read_hide_list(path: &Path) -> Result<Vec<String>, Error> {
+    let file = File::open(path).map_err(Error::Io)?;
+    let mut seen = HashSet::new();
+    let mut keys = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(Error::Io)?;
+        let key = line.trim();
+        if !key.is_empty() && seen.insert(key.to_owned()) {
+            keys.push(key.to_owned());
+        }
+    }
+    Ok(keys)
+}
+
+/// Hide each of `keys` in every .vrcset file that doesn't already hide them, and record each as a manual hide in that
+/// file's history
+pub fn import_hides(config: &Config, moderations_dir: &Path, history_dir: &Path, keys: &[String], log: &mut LogFile) -> Result<ImportStats, Error> {
+    let options = Options::default();
+    let mut run = Run {
+        log,
+        config,
+        history_dir,
+        options: &options,
+        now: current_timestamp(),
+    };
+    let mut stats = ImportStats::default();
+    for dir in moderations_dirs(moderations_dir)? {
+        for dir_entry in fs::read_dir(dir.path.as_path()).map_err(Error::Io)? {
+            let vrcset_path = dir_entry.map_err(Error::Io)?.path();
+            let is_vrcset = vrcset_path.extension().is_some_and(|extension| extension == "vrcset") && vrcset_path.is_file();
+            let included = vrcset_path.file_name().is_some_and(|filename| config.includes_file(&filename.to_string_lossy()));
+            if is_vrcset && included {
+                let file_stats = run.import_file(vrcset_path.as_path(), dir.account.as_deref(), keys)?;
+                stats.added += file_stats.added;
+                stats.present += file_stats.present;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Forget everything known about `key`: remove their show or hide line from every .vrcset file, and record a manual
+/// reset in the history of each file that knew them. Returns the number of files the user was found in.
+pub fn reset_user(config: &Config, moderations_dir: &Path, history_dir: &Path, key: &str, log: &mut LogFile) -> Result<u32, Error> {
+    let options = Options::default();
+    let mut run = Run {
+        log,
+        config,
+        history_dir,
+        options: &options,
+        now: current_timestamp(),
+    };
+    let mut found: u32 = 0;
+    for dir in moderations_dirs(moderations_dir)? {
+        for dir_entry in fs::read_dir(dir.path.as_path()).map_err(Error::Io)? {
+            let vrcset_path = dir_entry.map_err(Error::Io)?.path();
+            let is_vrcset = vrcset_path.extension().is_some_and(|extension| extension == "vrcset") && vrcset_path.is_file();
+            let included = vrcset_path.file_name().is_some_and(|filename| config.includes_file(&filename.to_string_lossy()));
+            if is_vrcset && included && run.reset_file(vrcset_path.as_path(), dir.account.as_deref(), key)? {
+                found += 1;
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Path of the history file for a .vrcset file, or `None` if the filename has no extension. Files in an account folder
+/// have the account in front, which can't collide with a file outside any account folder as a stem never contains a `.`.
+pub fn history_path(history_dir: &Path, account: Option<&str>, vrcset_filename: &str) -> Option<PathBuf> {
+    let (stem, _extension) = vrcset_filename.split_once('.')?;
+    match account {
+        Some(account) => Some(history_dir.join(format!("{account}.{stem}.history"))),
+        None => Some(history_dir.join(format!("{stem}.history"))),
+    }
+}
+
+/// A .vrcset file named on the command line, either as `<NAME>` or as `<ACCOUNT>/<NAME>` for one in an account folder,
+/// with or without the `.vrcset` extension. This is how logs and stats name files, too.
+#[derive(Debug, PartialEq, Eq)]
+pub struct NamedVrcset {
+    /// name of the account folder, or `None` for the moderations directory itself
+    pub account: Option<String>,
+    /// filename including the `.vrcset` extension
+    pub filename: String,
+}
+
+impl NamedVrcset {
+    pub fn parse(name: &str) -> Self {
+        let (account, name) = match name.split_once(['/', '\\']) {
+            Some((account, name)) if account.starts_with(ACCOUNT_DIR_PREFIX) => (Some(account.to_owned()), name),
+            _ => (None, name),
+        };
+        let filename = if name.ends_with(".vrcset") {
+            name.to_owned()
+        } else {
+            format!("{name}.vrcset")
+        };
+        Self { account, filename }
+    }
+
+    pub fn vrcset_path(&self, moderations_dir: &Path) -> PathBuf {
+        match &self.account {
+            Some(account) => moderations_dir.join(account).join(self.filename.as_str()),
+            None => moderations_dir.join(self.filename.as_str()),
+        }
+    }
+
+    /// how logs and stats name the file
+    pub fn display_name(&self) -> String {
+        display_name(self.account.as_deref(), &self.filename)
+    }
+
+    /// see [`history_path`]
+    pub fn history_path(&self, history_dir: &Path) -> Option<PathBuf> {
+        history_path(history_dir, self.account.as_deref(), &self.filename)
+    }
+}
+
+struct Run<'a> {
+    log: &'a mut LogFile,
+    config: &'a Config,
+    history_dir: &'a Path,
+    options: &'a Options,
+    /// unix timestamp in seconds that everything recorded by this run is stamped with
+    now: u64,
+}
+
+impl Run<'_> {
+    /// process a *.vrcset file from `account`'s folder, returning `None` if `dir_entry` isn't one
+    fn process_file(&mut self, dir_entry: DirEntry, account: Option<&str>) -> Result<Option<FileRunStats>, Error> {
+        let config = self.config;
+        if dir_entry.file_name().as_encoded_bytes().ends_with(b".vrcset") {
+            let vrcset_path = dir_entry.path();
+            if vrcset_path.is_file() {
+                // calculate some paths and filenames
+                fs::create_dir_all(self.history_dir).map_err(Error::Io)?;
+                let vrcset_os_filename = vrcset_path.file_name().unwrap();
+                let vrcset_filename = vrcset_os_filename.to_str().ok_or_else(|| Error::BadFilename(vrcset_os_filename.to_owned()))?;
+                let display_name = display_name(account, vrcset_filename);
+                let transaction_log_path = history_path(self.history_dir, account, vrcset_filename)
+                    .ok_or_else(|| Error::BadFilename(vrcset_os_filename.to_owned()))?;
+
+                // read ordered transaction log counting shows since last hide into a map
+                let mut transaction_log_file = open_history(transaction_log_path.as_path())?;
+                let mut complete_history = true; // false if any transactions were left out of the map
+                let mut shows_since_last_hide = if transaction_log_path.is_file() {
+                    let size = transaction_log_file.metadata().map_err(Error::Io)?.len();
+                    let history = if config.stream_history_over_bytes != 0 && size > config.stream_history_over_bytes {
+                        writeln!(self.log, "{} is {size} bytes, over stream_history_over_bytes; only tracking users present in {display_name}",
+                                 transaction_log_path.display());
+                        None
+                    } else {
+                        let on_skipped = |e| {
+                            complete_history = false;
+                            self.log.log(Level::Debug, format_args!("skipping unknown transaction {e:?}"));
+                        };
+                        match transaction::read_log(&transaction_log_file, config, self.now, on_skipped) {
+                            Err(Error::HistoryTooLarge) => {
+                                writeln!(self.log, "WARNING: {} has more than {} users; only tracking users present in {display_name}. Consider compacting your history.",
+                                         transaction_log_path.display(), config.max_history_keys);
+                                None
+                            }
+                            Err(Error::HistoryChecksum(line)) => {
+                                // counts from a damaged history could hide anyone, so leave the vrcset file alone entirely
+                                writeln!(self.log, "WARNING: {} failed its integrity check at line {line}; it may be truncated or corrupted. Not touching {display_name} until it is fixed or removed.",
+                                         transaction_log_path.display());
+                                return Err(Error::HistoryChecksum(line));
+                            }
+                            shows_since_last_hide => Some(shows_since_last_hide?),
+                        }
+                    };
+                    let history = match history {
+                        Some(history) => history,
+                        None => {
+                            // only the users in the vrcset file matter for the streaming pass, so track just those
+                            complete_history = false;
+                            let keys = read_vrcset_keys(vrcset_path.as_path(), config.max_line_bytes)?;
+                            transaction::read_log_for_keys(&transaction_log_file, &keys, config, self.now, |e| self.log.log(Level::Debug, format_args!("skipping unknown transaction {e:?}")))?
+                        }
+                    };
+                    Some(history)
+                } else {
+                    None
+                };
+
+                // a partial map can't be compacted without losing whatever was left out of it
+                if let Some(history) = shows_since_last_hide.as_ref().filter(|_| complete_history && !self.options.dry_run) {
+                    let size = transaction_log_file.metadata().map_err(Error::Io)?.len();
+                    if config.compact_history_over_bytes != 0 && size > config.compact_history_over_bytes {
+                        let transactions = transaction::compact(history);
+                        writeln!(self.log, "compacting {} from {size} bytes to {} transactions", transaction_log_path.display(), transactions.len());
+                        atomic::replace_file(transaction_log_path.as_path(), |file| transaction::write_log(file, transactions))?;
+                        transaction_log_file = open_history(transaction_log_path.as_path())?;
+                    }
+                }
+
+                self.log.log(Level::Debug, format_args!("processing {display_name} with history {}", transaction_log_path.display()));
+
+                // during warm-up we only watch what the user does manually
+                let stats_path = transaction_log_path.with_extension("state.json");
+                let stats = (config.track_stats || config.warmup_runs > 0).then(|| self.load_stats(stats_path.as_path()));
+                let observe_only = stats.as_ref().is_some_and(|stats| stats.runs < u64::from(config.warmup_runs));
+                if observe_only {
+                    writeln!(self.log, "warm-up run for {display_name}: only recording manual changes");
+                }
+
+                // read the vrcset file; changes are written to a replacement file afterwards
+                let mut removed: u32 = 0; // track removed lines
+                let mut pruned: u32 = 0; // track removed hidden lines
+                // hidden users with no activity since this time are pruned, if pruning is enabled
+                let prune_before = (config.prune_hides_after_days != 0 && !observe_only)
+                    .then(|| self.now.saturating_sub(u64::from(config.prune_hides_after_days) * 60 * 60 * 24));
+                // users auto reset after this time are retained rather than reset again, if there is a cooldown
+                let cooldown_after = (config.auto_hide_cooldown_secs != 0)
+                    .then(|| self.now.saturating_sub(config.auto_hide_cooldown_secs));
+                let mut retained: u32 = 0; // track retained lines that we would have normally removed, if not for the threshold
+                let mut diff = FileDiff::default(); // the users behind removed, retained, and shown, if asked for
+                let collect_diff = self.options.diff;
+                let mut pending_transactions: Vec<Transaction> = Vec::new(); // track difference between previous data and current data
+                let Vrcset { lines, line_ending, too_long } = self.read_vrcset(vrcset_path.as_path())?;
+                let lines_to_keep: Vec<moderation::Line> = self.collapse_duplicates(lines, &display_name).into_iter().filter(|line| {
+                    // number of times user was shown since last hide OR None if there is no data. Other moderations
+                    // leave the user's history for their show or hide line, if they have one.
+                    let shows = match line.value {
+                        moderation::Value::Other(_) => None,
+                        moderation::Value::Hide | moderation::Value::Show => shows_since_last_hide.as_mut().and_then(|map| map.remove(&line.key)),
+                    };
+
+                    match line.value {
+                        moderation::Value::Hide => { // we read a Hide from the vrcset file
+                            if shows.as_ref().map(|shows| !shows.is_hidden()).unwrap_or(true) {
+                                // if user was NOT last known to be hidden, record this manual hide
+                                pending_transactions.push(Transaction::new(line.key.to_owned(), TransactionValue::ManualHide, self.now));
+                                let hides = shows.as_ref().map_or(0, ShowHideCount::manual_hides) + 1;
+                                if config.unstick_after_hides != 0 && hides == config.unstick_after_hides {
+                                    writeln!(self.log, "{} has been hidden {hides} times without sticking in {display_name}; they now need twice as many shows", line.key);
+                                }
+                                true // retain hidden user entries
+                            } else if shows.and_then(|shows| shows.last_timestamp()).zip(prune_before).is_some_and(|(last, prune_before)| last < prune_before) {
+                                // user has been hidden with no activity for long enough; reset the user
+                                pending_transactions.push(Transaction::new(line.key.to_owned(), TransactionValue::AutoReset, self.now));
+                                pruned += 1;
+                                false // remove entry
+                            } else {
+                                true // retain hidden user entries
+                            }
+                        }
+                        moderation::Value::Show => { // we read a Show from the vrcset file
+                            let threshold = transaction::threshold_for(config, &line.key, shows.as_ref());
+                            let sticky = transaction::record_show(&line.key, shows.as_ref(), threshold, self.now, &mut pending_transactions);
+
+                            // check if we've shown this user enough times that the show should stick
+                            if observe_only {
+                                true // still warming up; leave the entry alone
+                            } else if !config.auto_hides(&line.key) || config.is_pinned(&line.key) || config.is_exempt(&line.key) || shows.as_ref().is_some_and(|shows| shows.is_pinned()) {
+                                retained += 1;
+                                if collect_diff {
+                                    diff.retained.push(line.key.clone());
+                                }
+                                true // pinned and exempt users are always retained, as is everyone when auto hide is disabled
+                            } else if !sticky && cooldown_after.zip(shows.as_ref()).is_some_and(|(cooldown_after, shows)| shows.is_cooling_down(cooldown_after)) {
+                                // not enough shows, but reset too recently to reset again
+                                retained += 1;
+                                if collect_diff {
+                                    diff.retained.push(line.key.clone());
+                                }
+                                true // retain entry
+                            } else if !sticky {
+                                // not enough shows; reset the user
+                                pending_transactions.push(Transaction::new(line.key.to_owned(), TransactionValue::AutoReset, self.now));
+                                removed += 1;
+                                if collect_diff {
+                                    diff.removed.push(line.key.clone());
+                                }
+                                false // remove entry
+                            } else {
+                                // enough shows; retain the user, and pin them if they've been shown enough for that too
+                                transaction::record_pin(&line.key, shows.as_ref(), config.auto_pin_after_shows, self.now, &mut pending_transactions);
+                                retained += 1;
+                                if collect_diff {
+                                    diff.retained.push(line.key.clone());
+                                }
+                                true // retain entry
+                            }
+                        }
+                        moderation::Value::Other(_) => true, // not a show or hide, so not ours to touch
+                    }
+                }).collect();
+
+                // handle any remaining entries in the map
+                let mut shown: u32 = 0;
+                let mut lines_to_show = Vec::new();
+                if let Some(shows_since_last_hide) = shows_since_last_hide {
+
+                    let (default_lines, non_default_lines): (Vec<_>, Vec<_>) = shows_since_last_hide.into_iter()
+                        .partition(|(_, state)| state.is_default());
+
+                    // handle manual non-default -> default transitions
+                    non_default_lines.into_iter()
+                        .for_each(|(key, _)| pending_transactions.push(Transaction::new(key, TransactionValue::ManualReset, self.now)));
+
+                    // handle case where the show threshold has lowered: we need to go back and re-show previously reset users
+                    let mut users_to_show: Vec<_> = default_lines.into_iter()
+                        .filter(|(key, show_hide_count)| {
+                            // a pinned or exempt user who has been shown before was only reset by us, so bring them back too
+                            let pinned = (config.is_pinned(key) || config.is_exempt(key)) && show_hide_count.count() != 0;
+                            // with auto hide disabled nobody was reset for lack of shows, so nobody needs bringing back. Users
+                            // who came unstuck have to be shown again by hand.
+                            let reached_threshold = config.auto_hides(key)
+                                && !show_hide_count.is_unstuck(config.unstick_after_hides)
+                                && show_hide_count.count() >= config.threshold_for(key);
+                            !observe_only && (pinned || reached_threshold)
+                        })
+                        .collect();
+                    if config.max_auto_show_per_run != 0 && users_to_show.len() > config.max_auto_show_per_run {
+                        // prefer the users with the most shows; the rest stay reset and get re-evaluated next run
+                        users_to_show.sort_unstable_by_key(|(_, show_hide_count)| Reverse(show_hide_count.count()));
+                        let deferred = users_to_show.split_off(config.max_auto_show_per_run).len();
+                        writeln!(self.log, "deferring {deferred} shown user entries for {display_name} due to max_auto_show_per_run");
+                    }
+                    lines_to_show = users_to_show.into_iter()
+                        .map(|(key, _)| {
+                            shown += 1;
+                            if collect_diff {
+                                diff.shown.push(key.clone());
+                            }
+                            pending_transactions.push(Transaction::new(key.clone(), TransactionValue::AutoShow, self.now));
+                            moderation::Line::new(key, moderation::Value::Show)
+                        }).collect();
+                }
+
+                let file_stats = FileRunStats {
+                    removed,
+                    retained,
+                    pruned,
+                    shown,
+                    transactions: u32::try_from(pending_transactions.len()).map_err(Error::U64FromInt)?,
+                    diff,
+                };
+                if self.options.dry_run {
+                    for transaction in pending_transactions.iter().filter(|transaction| transaction.value.is_automatic()) {
+                        writeln!(self.log, "dry run: would record {}", transaction.serialize().trim_end());
+                    }
+                    writeln!(self.log, "dry run: would remove {removed}, retain {retained}, prune {pruned}, and add {shown} entries in {display_name}");
+                    return Ok(Some(file_stats));
+                }
+
+                if !self.confirm(&display_name, &pending_transactions)? {
+                    // only hooligan's own changes were declined; the shows and hides the user made still need recording,
+                    // or the next run would count them again
+                    pending_transactions.retain(|transaction| !transaction.value.is_automatic());
+                    writeln!(self.log, "changes to {display_name} were declined; recording {} manual changes only", pending_transactions.len());
+                    let file_stats = FileRunStats {
+                        transactions: u32::try_from(pending_transactions.len()).map_err(Error::U64FromInt)?,
+                        ..FileRunStats::default()
+                    };
+                    transaction::write_run(&transaction_log_file, self.now, pending_transactions)?;
+                    return Ok(Some(file_stats));
+                }
+
+                let mut lines = lines_to_keep;
+                lines.extend(lines_to_show);
+                self.overwrite_lines(vrcset_path.as_path(), lines, line_ending, &too_long, config.buffer_writes)?;
+                writeln!(self.log, "removed {removed} and retained {retained} shown user entries from {display_name}");
+                if pruned != 0 {
+                    writeln!(self.log, "pruned {pruned} inactive hidden user entries from {display_name}");
+                }
+                if shown != 0 {
+                    writeln!(self.log, "added {shown} shown user entries to {display_name}");
+                }
+
+                // persist changes to transaction log
+                self.log.log(Level::Debug, format_args!("about to record {} transactions to {}", pending_transactions.len(), transaction_log_path.display()));
+                transaction::write_run(&transaction_log_file, self.now, pending_transactions)?;
+
+                // without track_stats the state file only exists to count warm-up runs, so it's left alone once they're over
+                if let Some(mut stats) = stats.filter(|_| config.track_stats || observe_only) {
+                    stats.record_run(self.now, removed, retained, shown);
+                    self.save_stats(stats_path.as_path(), &stats);
+                }
+                return Ok(Some(file_stats));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Put a .vrcset file back the way it was before the last run and forget that run's history. Returns false if
+    /// there was no run to undo. Lines that are put back go at the end of the file.
+    fn undo_file(&mut self, vrcset_path: &Path, account: Option<&str>) -> Result<bool, Error> {
+        let vrcset_os_filename = vrcset_path.file_name().unwrap_or_default();
+        let vrcset_filename = vrcset_os_filename.to_str().ok_or_else(|| Error::BadFilename(vrcset_os_filename.to_owned()))?;
+        let display_name = display_name(account, vrcset_filename);
+        let transaction_log_path = history_path(self.history_dir, account, vrcset_filename)
+            .ok_or_else(|| Error::BadFilename(vrcset_os_filename.to_owned()))?;
+        let last_run = match File::open(transaction_log_path.as_path()) {
+            Ok(file) => transaction::read_last_run(&file, self.config, |e| self.log.log(Level::Debug, format_args!("skipping unknown transaction {e:?}")))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let Some(last_run) = last_run else {
+            self.log.log(Level::Debug, format_args!("no run to undo for {display_name}"));
+            return Ok(false);
+        };
+
+        let Vrcset { mut lines, line_ending, too_long } = self.read_vrcset(vrcset_path)?;
+        let mut changes: u32 = 0;
+        for undo in last_run.undo {
+            let (key, value) = match undo {
+                Undo::Remove(key) => {
+                    let len = lines.len();
+                    lines.retain(|line| !(line.key == key && matches!(line.value, moderation::Value::Show)));
+                    changes += u32::from(lines.len() != len);
+                    continue;
+                }
+                Undo::Show(key) => (key, moderation::Value::Show),
+                Undo::Hide(key) => (key, moderation::Value::Hide),
+            };
+            // the user may have added them back themselves since
+            if !lines.iter().any(|line| line.key == key) {
+                lines.push(moderation::Line::new(key, value));
+                changes += 1;
+            }
+        }
+        self.overwrite_lines(vrcset_path, lines, line_ending, &too_long, self.config.buffer_writes)?;
+
+        // cut the run out of the history, so the next run sees the same history this one did
+        atomic::replace_file(transaction_log_path.as_path(), |mut file| {
+            let history_file = File::open(transaction_log_path.as_path()).map_err(Error::Io)?;
+            io::copy(&mut history_file.take(last_run.offset), &mut file).map_err(Error::Io)?;
+            Ok(())
+        })?;
+        match last_run.timestamp {
+            Some(timestamp) => writeln!(self.log, "undid {changes} changes to {display_name} from the run at {timestamp}"),
+            None => writeln!(self.log, "undid {changes} changes to {display_name}"),
+        }
+        Ok(true)
+    }
+
+    /// Hide each of `keys` the file doesn't already hide, and record them in its history as a run of their own. A user's
+    /// show line is replaced by the hide where it stands; users without a line get one at the end.
+    fn import_file(&mut self, vrcset_path: &Path, account: Option<&str>, keys: &[String]) -> Result<ImportStats, Error> {
+        let vrcset_os_filename = vrcset_path.file_name().unwrap_or_default();
+        let vrcset_filename = vrcset_os_filename.to_str().ok_or_else(|| Error::BadFilename(vrcset_os_filename.to_owned()))?;
+        let display_name = display_name(account, vrcset_filename);
+        let transaction_log_path = history_path(self.history_dir, account, vrcset_filename)
+            .ok_or_else(|| Error::BadFilename(vrcset_os_filename.to_owned()))?;
+
+        let Vrcset { lines, line_ending, too_long } = self.read_vrcset(vrcset_path)?;
+        // the last show or hide line for a user is the one that counts, as with duplicates in a normal run
+        let mut hidden: HashMap<&str, bool> = HashMap::new();
+        for line in &lines {
+            if matches!(line.value, moderation::Value::Hide | moderation::Value::Show) {
+                hidden.insert(line.key.as_str(), line.value == moderation::Value::Hide);
+            }
+        }
+        let mut stats = ImportStats::default();
+        let mut new_hides: Vec<&String> = Vec::new();
+        for key in keys {
+            if hidden.get(key.as_str()).copied().unwrap_or(false) {
+                stats.present += 1;
+            } else {
+                stats.added += 1;
+                new_hides.push(key);
+            }
+        }
+        if !new_hides.is_empty() {
+            let to_hide: HashSet<&str> = new_hides.iter().map(|key| key.as_str()).collect();
+            let mut replaced: HashSet<String> = HashSet::new();
+            // each new hide takes the place of the user's first show line, and any others are dropped
+            let mut lines: Vec<moderation::Line> = lines.into_iter()
+                .filter_map(|line| match line.value {
+                    moderation::Value::Hide | moderation::Value::Show if to_hide.contains(line.key.as_str()) => {
+                        replaced.insert(line.key.clone()).then(|| moderation::Line::new(line.key, moderation::Value::Hide))
+                    }
+                    _ => Some(line),
+                })
+                .collect();
+            lines.extend(new_hides.iter()
+                .filter(|key| !replaced.contains(key.as_str()))
+                .map(|key| moderation::Line::new((*key).clone(), moderation::Value::Hide)));
+            self.overwrite_lines(vrcset_path, lines, line_ending, &too_long, self.config.buffer_writes)?;
+
+            fs::create_dir_all(self.history_dir).map_err(Error::Io)?;
+            let transactions = new_hides.into_iter()
+                .map(|key| Transaction::new(key.clone(), TransactionValue::ManualHide, self.now))
+                .collect();
+            transaction::write_run(&open_history(transaction_log_path.as_path())?, self.now, transactions)?;
+        }
+        writeln!(self.log, "imported {} hides into {display_name}; {} were already hidden", stats.added, stats.present);
+        Ok(stats)
+    }
+
+    /// Remove `key`'s show or hide line and record a manual reset as a run of its own if the file or its history knew
+    /// them, so that undo can put the line back. Returns false if neither did.
+    fn reset_file(&mut self, vrcset_path: &Path, account: Option<&str>, key: &str) -> Result<bool, Error> {
+        let vrcset_os_filename = vrcset_path.file_name().unwrap_or_default();
+        let vrcset_filename = vrcset_os_filename.to_str().ok_or_else(|| Error::BadFilename(vrcset_os_filename.to_owned()))?;
+        let display_name = display_name(account, vrcset_filename);
+        let transaction_log_path = history_path(self.history_dir, account, vrcset_filename)
+            .ok_or_else(|| Error::BadFilename(vrcset_os_filename.to_owned()))?;
+
+        let known = match File::open(transaction_log_path.as_path()) {
+            Ok(file) => {
+                let keys = HashSet::from([key.to_owned()]);
+                let history = transaction::read_log_for_keys(&file, &keys, self.config, self.now, |e| self.log.log(Level::Debug, format_args!("skipping unknown transaction {e:?}")))?;
+                history.contains_key(key)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        let Vrcset { mut lines, line_ending, too_long } = self.read_vrcset(vrcset_path)?;
+        let len = lines.len();
+        lines.retain(|line| !(line.key == key && matches!(line.value, moderation::Value::Show | moderation::Value::Hide)));
+        let removed = lines.len() != len;
+        if !known && !removed {
+            return Ok(false);
+        }
+        if removed {
+            self.overwrite_lines(vrcset_path, lines, line_ending, &too_long, self.config.buffer_writes)?;
+        }
+        fs::create_dir_all(self.history_dir).map_err(Error::Io)?;
+        let transactions = vec![Transaction::new(key.to_owned(), TransactionValue::ManualReset, self.now)];
+        transaction::write_reset_run(&open_history(transaction_log_path.as_path())?, self.now, transactions)?;
+        writeln!(self.log, "reset {key} in {display_name}");
+        Ok(true)
+    }
+
+    /// With `--confirm` in a terminal, list the changes hooligan is about to make and ask before making them. Always
+    /// true otherwise, so that running under Steam never blocks waiting for input.
+    fn confirm(&mut self, display_name: &str, pending_transactions: &[Transaction]) -> Result<bool, Error> {
+        if !self.options.confirm {
+            return Ok(true);
+        }
+        if !(io::stdin().is_terminal() && io::stdout().is_terminal()) {
+            writeln!(self.log, "ignoring --confirm because there is no terminal");
+            return Ok(true);
+        }
+        let changes: Vec<&Transaction> = pending_transactions.iter()
+            .filter(|transaction| transaction.value.is_automatic())
+            .collect();
+        if changes.is_empty() {
+            return Ok(true);
+        }
+
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{display_name}:").map_err(Error::Io)?;
+        for transaction in changes {
+            writeln!(stdout, "  {}", transaction.serialize().trim_end()).map_err(Error::Io)?;
+        }
+        write!(stdout, "apply these changes? [y/N] ").map_err(Error::Io)?;
+        stdout.flush().map_err(Error::Io)?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).map_err(Error::Io)?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    }
+
+    /// load the stats file. Failures are logged and otherwise ignored, as stats are not authoritative.
+    fn load_stats(&mut self, stats_path: &Path) -> FileStats {
+        match FileStats::load(stats_path) {
+            Ok(stats) => stats.unwrap_or_default(),
+            Err(e) => {
+                self.log.log(Level::Error, format_args!("failed to load stats from {} and starting over: {e:?}", stats_path.display()));
+                FileStats::default()
+            }
+        }
+    }
+
+    /// save the stats file. Failures are logged and otherwise ignored, as stats are not authoritative.
+    fn save_stats(&mut self, stats_path: &Path, stats: &FileStats) {
+        if let Err(e) = stats.save(stats_path) {
+            self.log.log(Level::Error, format_args!("failed to save stats to {}: {e:?}", stats_path.display()));
+        }
+    }
+
+    /// Read every line of a .vrcset file, omitting any that failed to parse
+    fn read_vrcset(&mut self, path: &Path) -> Result<Vrcset, Error> {
+        let file = File::open(path).map_err(Error::Io)?;
+        let mut line_reader = moderation::bounded_lines(BufReader::new(&file), self.config.max_line_bytes);
+        let lines = self.read_lines(parse_vrcset_lines(line_reader.by_ref()))?;
+        Ok(Vrcset {
+            lines,
+            line_ending: line_reader.line_ending(),
+            too_long: line_reader.too_long().to_vec(),
+        })
+    }
+
+    /// Read every line, omitting any that failed to parse. Reading everything up front means a read error is reported
+    /// before anything is written.
+    fn read_lines<T: Iterator<Item=Result<moderation::Line, Error>>>(&mut self, line_iter: T) -> Result<Vec<moderation::Line>, Error> {
+        let mut lines = Vec::new();
+        for line in line_iter {
+            match line {
+                Err(Error::ShowHideParse(e)) => writeln!(self.log, "omitting line due to parse error {e:?}"),
+                line => lines.push(line?),
+            }
+        }
+        Ok(lines)
+    }
+
+    /// Drop all but the last show or hide line for each user, as that's the one VRChat would have written most recently.
+    /// Other moderations, such as mutes, are never dropped. Each line that survives stays where it was.
+    fn collapse_duplicates(&mut self, lines: Vec<moderation::Line>, display_name: &str) -> Vec<moderation::Line> {
+        let mut seen = HashSet::new();
+        let mut duplicates = BTreeSet::new();
+        let mut collapsed: Vec<moderation::Line> = lines.into_iter().rev()
+            .filter(|line| {
+                if let moderation::Value::Other(_) = line.value {
+                    return true;
+                }
+                let first = seen.insert(line.key.clone());
+                if !first {
+                    duplicates.insert(line.key.clone());
+                }
+                first
+            })
+            .collect();
+        collapsed.reverse();
+        if !duplicates.is_empty() {
+            let duplicates: Vec<String> = duplicates.into_iter().collect();
+            writeln!(self.log, "WARNING: {display_name} has more than one line for {}; keeping only the last of each", duplicates.join(", "));
+        }
+        collapsed
+    }
+
+    /// Replace the contents of the file at `path`. The new contents go to a temporary file which is only moved over the
+    /// original once complete, so a failure partway leaves the original untouched. If `buffered`, the new contents are
+    /// serialized in memory first and written with a single call. The `too_long` byte ranges of the original are
+    /// appended to a `.quarantine` file beside it first, so lines too long to parse are never lost.
+    fn overwrite_lines(&mut self, path: &Path, lines: Vec<moderation::Line>, line_ending: LineEnding, too_long: &[Range<u64>], buffered: bool) -> Result<(), Error> {
+        if !too_long.is_empty() {
+            let quarantine_path = quarantine(path, too_long)?;
+            writeln!(self.log, "WARNING: moved {} lines longer than max_line_bytes from {} to {}", too_long.len(), path.display(), quarantine_path.display());
+        }
+        atomic::replace_file(path, |mut file| {
+            if buffered {
+                let buffer: String = lines.iter().map(|line| line.serialize_with(line_ending)).collect();
+                file.write_all(buffer.as_bytes()).map_err(Error::Io)
+            } else {
+                self.write_lines(file, lines.into_iter().map(Ok), line_ending).map(|_| ())
+            }
+        })
+    }
+
+    /// Write lines to `write`, returning the number of bytes written. Lines that failed to parse are omitted, but any
+    /// other error stops the write and is returned, leaving it to the caller to discard the partial output.
+    fn write_lines<W: Write, T: Iterator<Item=Result<moderation::Line, Error>>>(&mut self, write: W, line_iter: T, line_ending: LineEnding) -> Result<u64, Error> {
+        let mut writer = BufWriter::new(write);
+        let mut size: u64 = 0;
+        for line in line_iter {
+            match line {
+                Ok(line) => {
+                    let serialized = line.serialize_with(line_ending);
+                    writer.write_all(serialized.as_bytes()).map_err(Error::Io)?;
+                    size += u64::try_from(serialized.len()).map_err(Error::U64FromInt)?;
+                }
+                Err(Error::ShowHideParse(e)) => {
+                    writeln!(self.log, "omitting line due to parse error {e:?}");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        writer.flush().map_err(Error::Io)?;
+        Ok(size)
+    }
+}
+
+/// parse the lines of a .vrcset file, handling errors and dropping blank lines
+#[cfg(test)]
+fn vrcset_lines(file: &File, max_line_bytes: usize) -> impl Iterator<Item=Result<moderation::Line, Error>> + '_ {
+    parse_vrcset_lines(moderation::bounded_lines(BufReader::new(file), max_line_bytes))
+}
+
+/// parse lines read by [`moderation::bounded_lines`], handling errors and dropping blank lines
+fn parse_vrcset_lines<I: Iterator<Item=io::Result<Result<String, moderation::ParseError>>>>(lines: I) -> impl Iterator<Item=Result<moderation::Line, Error>> {
+    lines.filter_map(|maybe_line| {
+        match maybe_line {
+            Ok(Ok(line)) => moderation::Line::parse_non_blank(&line).map(|line| line.map_err(Error::ShowHideParse)),
+            Ok(Err(e)) => Some(Err(Error::ShowHideParse(e))), // overly long lines are omitted like any other corrupt line, and quarantined on write
+            Err(e) => Some(Err(Error::Io(e))),
+        }
+    })
+}
+
+/// The contents of a .vrcset file, as read by [`Run::read_vrcset`]
+struct Vrcset {
+    /// every line that parsed
+    lines: Vec<moderation::Line>,
+    /// the line ending most lines use, so that it can be kept when the file is written back
+    line_ending: LineEnding,
+    /// where each line longer than `max_line_bytes` was, so that it can be quarantined rather than lost
+    too_long: Vec<Range<u64>>,
+}
+
+/// Append the `ranges` of the file at `path` to a `.quarantine` file beside it, each ending in a newline, and return
+/// the quarantine file's path
+fn quarantine(path: &Path, ranges: &[Range<u64>]) -> Result<PathBuf, Error> {
+    let quarantine_path = atomic::sibling(path, QUARANTINE_EXTENSION);
+    let mut source = File::open(path).map_err(Error::Io)?;
+    let mut open_options = OpenOptions::new();
+    open_options.append(true);
+    open_options.create(true);
+    let mut writer = BufWriter::new(open_options.open(quarantine_path.as_path()).map_err(Error::Io)?);
+    for range in ranges {
+        source.seek(SeekFrom::Start(range.start)).map_err(Error::Io)?;
+        let mut line = Vec::new();
+        (&mut source).take(range.end - range.start).read_to_end(&mut line).map_err(Error::Io)?;
+        writer.write_all(&line).map_err(Error::Io)?;
+        if line.last() != Some(&b'\n') {
+            writer.write_all(b"\n").map_err(Error::Io)?;
+        }
+    }
+    writer.flush().map_err(Error::Io)?;
+    Ok(quarantine_path)
+}
+
+/// open a history file for reading and appending, creating it if needed
+fn open_history(path: &Path) -> Result<File, Error> {
+    let mut open_options = OpenOptions::new();
+    open_options.read(true);
+    open_options.append(true);
+    open_options.create(true);
+    open_options.open(path).map_err(Error::Io)
+}
+
+/// every key in a vrcset file, skipping lines that fail to parse
+fn read_vrcset_keys(path: &Path, max_line_bytes: usize) -> Result<HashSet<String>, Error> {
+    let file = File::open(path).map_err(Error::Io)?;
+    let mut keys = HashSet::new();
+    for line in parse_vrcset_lines(moderation::bounded_lines(BufReader::new(file), max_line_bytes)) {
+        match line {
+            Ok(line) => {
+                keys.insert(line.key);
+            }
+            Err(Error::ShowHideParse(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn test_run_all() {
+        let moderations_dir = TestDir::new("run-all-moderations");
+        let history_dir = TestDir::new("run-all-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 004\r\nusr_b 005\r\nusr_m 006\r\n").unwrap();
+        fs::write(moderations_dir.join("b.vrcset"), "usr_c 005\r\n").unwrap();
+        fs::write(moderations_dir.join("notes.txt"), "usr_d 005\r\n").unwrap();
+        fs::write(history_dir.join("b.history"), "usr_c MANUAL_SHOW\nusr_c AUTO_RESET\nusr_c MANUAL_SHOW\nusr_c AUTO_RESET\n").unwrap();
+
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.files.len(), 2);
+        assert_eq!(stats.files["a.vrcset"], FileRunStats { removed: 1, transactions: 3, ..FileRunStats::default() });
+        assert_eq!(stats.files["b.vrcset"], FileRunStats { retained: 1, transactions: 1, ..FileRunStats::default() });
+        assert_eq!((stats.removed(), stats.retained(), stats.pruned(), stats.shown()), (1, 1, 0, 0));
+        let expected = [
+            moderation::Line::new("usr_a".to_owned(), moderation::Value::Hide),
+            moderation::Line::new("usr_m".to_owned(), moderation::Value::Other(6)),
+        ].iter().map(moderation::Line::serialize).collect::<String>();
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), expected);
+        assert_eq!(fs::read_to_string(moderations_dir.join("notes.txt")).unwrap(), "usr_d 005\r\n");
+    }
+
+    #[test]
+    fn test_run_stats_serialize() {
+        let mut stats = RunStats::default();
+        assert_eq!(stats.serialize(5), "{\"timestamp\":5,\"files\":{},\"total\":{\"removed\":0,\"retained\":0,\"pruned\":0,\"shown\":0,\"transactions\":0}}\n");
+        stats.files.insert("a.vrcset".to_owned(), FileRunStats { removed: 1, retained: 2, pruned: 3, shown: 4, transactions: 10, ..FileRunStats::default() });
+        stats.files.insert("b.vrcset".to_owned(), FileRunStats { removed: 1, transactions: 2, ..FileRunStats::default() });
+        assert_eq!(stats.serialize(5), concat!(
+            "{\"timestamp\":5,\"files\":{",
+            "\"a.vrcset\":{\"removed\":1,\"retained\":2,\"pruned\":3,\"shown\":4,\"transactions\":10},",
+            "\"b.vrcset\":{\"removed\":1,\"retained\":0,\"pruned\":0,\"shown\":0,\"transactions\":2}},",
+            "\"total\":{\"removed\":2,\"retained\":2,\"pruned\":3,\"shown\":4,\"transactions\":12}}\n"));
+    }
+
+    #[test]
+    fn test_run_all_distinct_histories() {
+        let moderations_dir = TestDir::new("run-all-distinct-moderations");
+        let history_dir = TestDir::new("run-all-distinct-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\nusr_b 005\r\n").unwrap();
+        fs::write(moderations_dir.join("b.vrcset"), "usr_a 005\r\nusr_b 005\r\n").unwrap();
+        fs::write(history_dir.join("a.history"), "usr_a MANUAL_SHOW\nusr_a AUTO_RESET\nusr_a MANUAL_SHOW\nusr_a AUTO_RESET\n").unwrap();
+        fs::write(history_dir.join("b.history"), "usr_b MANUAL_SHOW\nusr_b AUTO_RESET\nusr_b MANUAL_SHOW\nusr_b AUTO_RESET\n").unwrap();
+
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.files["a.vrcset"], FileRunStats { removed: 1, retained: 1, transactions: 3, ..FileRunStats::default() });
+        assert_eq!(stats.files["b.vrcset"], FileRunStats { removed: 1, retained: 1, transactions: 3, ..FileRunStats::default() });
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), moderation::Line::new("usr_a".to_owned(), moderation::Value::Show).serialize());
+        assert_eq!(fs::read_to_string(moderations_dir.join("b.vrcset")).unwrap(), moderation::Line::new("usr_b".to_owned(), moderation::Value::Show).serialize());
+        let history = fs::read_to_string(history_dir.join("a.history")).unwrap();
+        let mut new_lines: Vec<&str> = history.lines().skip(4).filter(|line| !transaction::is_run_marker(line)).map(|line| line.rsplit_once(' ').unwrap().0).collect();
+        new_lines.sort_unstable();
+        assert_eq!(new_lines, ["usr_a MANUAL_SHOW", "usr_b AUTO_RESET", "usr_b MANUAL_SHOW"]);
+        let history = fs::read_to_string(history_dir.join("b.history")).unwrap();
+        let mut new_lines: Vec<&str> = history.lines().skip(4).filter(|line| !transaction::is_run_marker(line)).map(|line| line.rsplit_once(' ').unwrap().0).collect();
+        new_lines.sort_unstable();
+        assert_eq!(new_lines, ["usr_a AUTO_RESET", "usr_a MANUAL_SHOW", "usr_b MANUAL_SHOW"]);
+    }
+
+    #[test]
+    fn test_run_all_duplicate_keys() {
+        let moderations_dir = TestDir::new("duplicate-moderations");
+        let history_dir = TestDir::new("duplicate-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\nusr_b 004\r\nusr_a 004\r\nusr_c 004\r\nusr_c 005\r\n").unwrap();
+
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.files["a.vrcset"], FileRunStats { removed: 1, transactions: 4, ..FileRunStats::default() });
+        let expected = [
+            moderation::Line::new("usr_b".to_owned(), moderation::Value::Hide),
+            moderation::Line::new("usr_a".to_owned(), moderation::Value::Hide),
+        ].iter().map(moderation::Line::serialize).collect::<String>();
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), expected);
+        let history = fs::read_to_string(history_dir.join("a.history")).unwrap();
+        let transactions: Vec<&str> = history.lines().skip(1).map(|line| line.rsplit_once(' ').unwrap().0).collect();
+        assert_eq!(transactions, ["usr_b MANUAL_HIDE", "usr_a MANUAL_HIDE", "usr_c MANUAL_SHOW", "usr_c AUTO_RESET"]);
+    }
+
+    #[test]
+    fn test_run_all_duplicate_keys_other_moderations() {
+        let moderations_dir = TestDir::new("duplicate-other-moderations");
+        let history_dir = TestDir::new("duplicate-other-history");
+        // every user has some other moderation besides their show lines, and usr_b has two show lines
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 001\r\nusr_a 005\r\nusr_b 005\r\nusr_b 002\r\nusr_b 005\r\nusr_c 001\r\n").unwrap();
+        fs::write(history_dir.join("a.history"), "usr_a MANUAL_SHOW\nusr_a MANUAL_SHOW\nusr_a MANUAL_SHOW\n").unwrap();
+
+        let options = Options { diff: true, ..Options::default() };
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &options, &mut LogFile::new(io::sink())).unwrap();
+        let diff = &stats.files["a.vrcset"].diff;
+        assert_eq!(diff.retained, ["usr_a"]);
+        assert_eq!(diff.removed, ["usr_b"]);
+        let expected = [
+            moderation::Line::new("usr_a".to_owned(), moderation::Value::Other(1)),
+            moderation::Line::new("usr_a".to_owned(), moderation::Value::Show),
+            moderation::Line::new("usr_b".to_owned(), moderation::Value::Other(2)),
+            moderation::Line::new("usr_c".to_owned(), moderation::Value::Other(1)),
+        ].iter().map(moderation::Line::serialize).collect::<String>();
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_run_all_diff() {
+        let moderations_dir = TestDir::new("diff-moderations");
+        let history_dir = TestDir::new("diff-history");
+        let vrcset = "usr_a 005\r\nusr_b 004\r\nusr_c 005\r\n";
+        let history = "usr_c MANUAL_SHOW 1\nusr_c MANUAL_SHOW 1\nusr_c MANUAL_SHOW 1\nusr_d MANUAL_SHOW 1\nusr_d MANUAL_SHOW 1\nusr_d MANUAL_SHOW 1\nusr_d AUTO_RESET 1\n";
+        fs::write(moderations_dir.join("a.vrcset"), vrcset).unwrap();
+        fs::write(history_dir.join("a.history"), history).unwrap();
+
+        let options = Options {
+            dry_run: true,
+            diff: true,
+            ..Options::default()
+        };
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &options, &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.diff_report(), "a.vrcset:\n  - usr_a\n  = usr_c\n  + usr_d\n");
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), vrcset);
+
+        // without --diff only the counts are kept
+        let options = Options {
+            dry_run: true,
+            ..Options::default()
+        };
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &options, &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.files["a.vrcset"].removed, 1);
+        assert_eq!(stats.diff_report(), "");
+    }
+
+    #[test]
+    fn test_undo_all() {
+        let moderations_dir = TestDir::new("undo-moderations");
+        let history_dir = TestDir::new("undo-history");
+        let vrcset = "usr_a 005\r\nusr_b 004\r\nusr_c 005\r\n";
+        let history = "usr_c MANUAL_SHOW 1\nusr_c MANUAL_SHOW 1\nusr_c MANUAL_SHOW 1\nusr_d MANUAL_SHOW 1\nusr_d MANUAL_SHOW 1\nusr_d MANUAL_SHOW 1\nusr_d AUTO_RESET 1\n";
+        fs::write(moderations_dir.join("a.vrcset"), vrcset).unwrap();
+        fs::write(history_dir.join("a.history"), history).unwrap();
+
+        let mut config = Config::default();
+        config.buffer_writes = true;
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.files["a.vrcset"], FileRunStats { removed: 1, retained: 1, shown: 1, transactions: 4, ..FileRunStats::default() });
+
+        assert_eq!(undo_all(&config, moderations_dir.as_path(), history_dir.as_path(), &mut LogFile::new(io::sink())).unwrap(), 1);
+        let mut lines: Vec<moderation::Line> = vrcset_lines(&File::open(moderations_dir.join("a.vrcset")).unwrap(), config.max_line_bytes)
+            .map(Result::unwrap)
+            .collect();
+        lines.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        let expected = [
+            moderation::Line::new("usr_a".to_owned(), moderation::Value::Show),
+            moderation::Line::new("usr_b".to_owned(), moderation::Value::Hide),
+            moderation::Line::new("usr_c".to_owned(), moderation::Value::Show),
+        ];
+        assert_eq!(lines.iter().map(moderation::Line::serialize).collect::<String>(), expected.iter().map(moderation::Line::serialize).collect::<String>());
+        assert_eq!(fs::read_to_string(history_dir.join("a.history")).unwrap(), history);
+
+        // that run is gone now, and there's nothing before it to undo
+        assert_eq!(undo_all(&config, moderations_dir.as_path(), history_dir.as_path(), &mut LogFile::new(io::sink())).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_undo_all_excluded() {
+        let moderations_dir = TestDir::new("undo-excluded-moderations");
+        let history_dir = TestDir::new("undo-excluded-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\n").unwrap();
+        fs::write(moderations_dir.join("b.vrcset"), "usr_a 005\r\n").unwrap();
+        let mut config = Config::default();
+        run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        let history = fs::read_to_string(history_dir.join("b.history")).unwrap();
+
+        config.exclude_glob = Some("b.*".to_owned());
+        assert_eq!(undo_all(&config, moderations_dir.as_path(), history_dir.as_path(), &mut LogFile::new(io::sink())).unwrap(), 1);
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), moderation::Line::new("usr_a".to_owned(), moderation::Value::Show).serialize());
+        assert_eq!(fs::read_to_string(moderations_dir.join("b.vrcset")).unwrap(), "");
+        assert_eq!(fs::read_to_string(history_dir.join("b.history")).unwrap(), history);
+    }
+
+    #[test]
+    fn test_import_hides() {
+        let moderations_dir = TestDir::new("import-moderations");
+        let history_dir = TestDir::new("import-history");
+        let hide_list_dir = TestDir::new("import-list");
+        let hide_list_path = hide_list_dir.join("hides.txt");
+        // usr_a is shown, usr_b hidden, and usr_e has only some other moderation
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\nusr_e 001\r\nusr_b 004").unwrap();
+        fs::write(hide_list_path.as_path(), "usr_b\n\nusr_c\r\n  \nusr_a\nusr_c\nusr_d\nusr_e\n").unwrap();
+
+        let keys = read_hide_list(hide_list_path.as_path()).unwrap();
+        assert_eq!(keys, ["usr_b", "usr_c", "usr_a", "usr_d", "usr_e"]);
+        let stats = import_hides(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &keys, &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats, ImportStats { added: 4, present: 1 });
+        let expected = [
+            moderation::Line::new("usr_a".to_owned(), moderation::Value::Hide),
+            moderation::Line::new("usr_e".to_owned(), moderation::Value::Other(1)),
+            moderation::Line::new("usr_b".to_owned(), moderation::Value::Hide),
+            moderation::Line::new("usr_c".to_owned(), moderation::Value::Hide),
+            moderation::Line::new("usr_d".to_owned(), moderation::Value::Hide),
+            moderation::Line::new("usr_e".to_owned(), moderation::Value::Hide),
+        ].iter().map(moderation::Line::serialize).collect::<String>();
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), expected);
+        // the hides are a run of their own, so they show up as one in the timeline
+        let history = fs::read_to_string(history_dir.join("a.history")).unwrap();
+        assert!(transaction::is_run_marker(history.lines().next().unwrap()));
+        let transactions: Vec<&str> = history.lines().skip(1).map(|line| line.rsplit_once(' ').unwrap().0).collect();
+        assert_eq!(transactions, ["usr_c MANUAL_HIDE", "usr_a MANUAL_HIDE", "usr_d MANUAL_HIDE", "usr_e MANUAL_HIDE"]);
+
+        // importing again changes nothing
+        let stats = import_hides(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &keys, &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats, ImportStats { added: 0, present: 5 });
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), expected);
+        assert_eq!(fs::read_to_string(history_dir.join("a.history")).unwrap(), history);
+    }
+
+    #[test]
+    fn test_reset_user() {
+        let moderations_dir = TestDir::new("reset-moderations");
+        let history_dir = TestDir::new("reset-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\nusr_b 004\r\n").unwrap();
+        fs::write(moderations_dir.join("b.vrcset"), "usr_b 005\r\n").unwrap();
+        fs::write(history_dir.join("a.history"), "usr_a MANUAL_SHOW 1\nusr_a MANUAL_SHOW 1\nusr_a MANUAL_SHOW 1\nusr_a MANUAL_SHOW 1\n").unwrap();
+
+        let config = Config::default();
+        let history = || transaction::read_log(&File::open(history_dir.join("a.history")).unwrap(), &config, current_timestamp(), |_| {}).unwrap();
+        assert!(history()["usr_a"].is_sticky(config.auto_hide_threshold));
+
+        let found = reset_user(&config, moderations_dir.as_path(), history_dir.as_path(), "usr_a", &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(found, 1);
+        assert!(history()["usr_a"].is_default());
+        assert_eq!(history()["usr_a"].count(), 0);
+        let expected = moderation::Line::new("usr_b".to_owned(), moderation::Value::Hide).serialize();
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), expected);
+        assert_eq!(fs::read_to_string(moderations_dir.join("b.vrcset")).unwrap(), "usr_b 005\r\n");
+
+        // a user nobody knows about is reported as not found, and nothing is recorded for them
+        let found = reset_user(&config, moderations_dir.as_path(), history_dir.as_path(), "usr_z", &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(found, 0);
+        assert!(!history_dir.join("b.history").exists());
+    }
+
+    #[test]
+    fn test_reset_user_undo() {
+        let moderations_dir = TestDir::new("reset-undo-moderations");
+        let history_dir = TestDir::new("reset-undo-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\nusr_b 004\r\nusr_c 005\r\n").unwrap();
+        fs::write(history_dir.join("a.history"), "usr_a MANUAL_SHOW 1\nusr_a MANUAL_SHOW 1\nusr_a MANUAL_SHOW 1\nusr_a MANUAL_SHOW 1\n").unwrap();
+        let config = Config::default();
+        let sorted_lines = || {
+            let mut lines: Vec<String> = vrcset_lines(&File::open(moderations_dir.join("a.vrcset")).unwrap(), config.max_line_bytes)
+                .map(|line| line.unwrap().serialize())
+                .collect();
+            lines.sort_unstable();
+            lines
+        };
+
+        // usr_a is sticky, so the run only removes usr_c
+        run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        let run_lines = sorted_lines();
+        let run_history = fs::read_to_string(history_dir.join("a.history")).unwrap();
+        assert_eq!(reset_user(&config, moderations_dir.as_path(), history_dir.as_path(), "usr_a", &mut LogFile::new(io::sink())).unwrap(), 1);
+        assert_eq!(sorted_lines().len(), 1);
+
+        // undo takes back only the reset, leaving the run before it alone
+        assert_eq!(undo_all(&config, moderations_dir.as_path(), history_dir.as_path(), &mut LogFile::new(io::sink())).unwrap(), 1);
+        assert_eq!(sorted_lines(), run_lines);
+        assert_eq!(fs::read_to_string(history_dir.join("a.history")).unwrap(), run_history);
+        assert!(transaction::read_log(&File::open(history_dir.join("a.history")).unwrap(), &config, current_timestamp(), |_| {}).unwrap()["usr_a"].is_sticky(config.auto_hide_threshold));
+
+        // then the run itself
+        assert_eq!(undo_all(&config, moderations_dir.as_path(), history_dir.as_path(), &mut LogFile::new(io::sink())).unwrap(), 1);
+        assert_eq!(sorted_lines().len(), 3);
+    }
+
+    #[test]
+    fn test_run_all_skips_failed_file() {
+        let moderations_dir = TestDir::new("skip-failed-moderations");
+        let history_dir = TestDir::new("skip-failed-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\n").unwrap();
+        fs::write(moderations_dir.join("b.vrcset"), "usr_b 005\r\n").unwrap();
+        // a's history can't be opened as a file
+        fs::create_dir_all(history_dir.join("a.history")).unwrap();
+
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert!(!stats.files.contains_key("a.vrcset"));
+        assert_eq!(stats.files["b.vrcset"].removed, 1);
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), "usr_a 005\r\n");
+    }
+
+    /// accepts `remaining` bytes, then fails every write
+    struct FailingWriter {
+        remaining: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.remaining == 0 {
+                return Err(io::Error::other("disk full"));
+            }
+            let len = buf.len().min(self.remaining);
+            self.remaining -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_lines_error() {
+        let options = Options::default();
+        let mut log = LogFile::new(io::sink());
+        let mut run = Run {
+            log: &mut log,
+            config: &Config::default(),
+            history_dir: Path::new(""),
+            options: &options,
+            now: current_timestamp(),
+        };
+        let lines = || (0..1000).map(|i| Ok(moderation::Line::new(format!("usr_{i}"), moderation::Value::Show)));
+
+        let result = run.write_lines(FailingWriter { remaining: 100 }, lines(), LineEnding::CrLf);
+        assert!(matches!(result, Err(Error::Io(_))));
+        assert!(run.write_lines(FailingWriter { remaining: usize::MAX }, lines(), LineEnding::CrLf).is_ok());
+
+        // errors coming from the lines themselves stop the write too, except for lines that failed to parse
+        let lines = [
+            Err(Error::ShowHideParse(moderation::ParseError::BadSplit(String::new()))),
+            Ok(moderation::Line::new("usr_a".to_owned(), moderation::Value::Show)),
+            Err(Error::Io(io::Error::other("read failed"))),
+        ];
+        let result = run.write_lines(FailingWriter { remaining: usize::MAX }, lines.into_iter(), LineEnding::CrLf);
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_run_all_auto_hide_disabled() {
+        let moderations_dir = TestDir::new("disabled-moderations");
+        let history_dir = TestDir::new("disabled-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\nusr_b 004\r\nusr_c 005\r\n").unwrap();
+        // usr_d was reset before auto hide was disabled, and stays that way
+        fs::write(history_dir.join("a.history"), "usr_a MANUAL_SHOW 1\nusr_d MANUAL_SHOW 1\nusr_d AUTO_RESET 1\n").unwrap();
+
+        let keys = || -> Vec<String> {
+            vrcset_lines(&File::open(moderations_dir.join("a.vrcset")).unwrap(), 1024).map(|line| line.unwrap().key).collect()
+        };
+
+        let mut config = Config::default();
+        config.auto_hide_threshold = 0;
+        for _ in 0..2 {
+            let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+            assert_eq!(stats.removed(), 0);
+            assert_eq!(stats.shown(), 0);
+            assert_eq!(stats.retained(), 2);
+        }
+        assert_eq!(keys(), ["usr_a", "usr_b", "usr_c"]);
+        let history = fs::read_to_string(history_dir.join("a.history")).unwrap();
+        assert_eq!(history.matches("AUTO_RESET").count(), 1);
+        assert!(!history.contains("AUTO_SHOW"));
+
+        // a per-user threshold of 0 exempts just that user
+        config.auto_hide_threshold = 3;
+        config.apply_override("threshold.usr_c=0").unwrap();
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.removed(), 1);
+        assert_eq!(keys(), ["usr_b", "usr_c"]);
+    }
+
+    #[test]
+    fn test_run_all_unstuck() {
+        let moderations_dir = TestDir::new("unstuck-moderations");
+        let history_dir = TestDir::new("unstuck-history");
+        // both users were hidden twice without sticking, then built up 3 shows. usr_a is being shown again, and usr_b
+        // was reset.
+        let cycles = "MANUAL_SHOW 1\nMANUAL_HIDE 1\nMANUAL_SHOW 1\nMANUAL_HIDE 1\nMANUAL_SHOW 1\nMANUAL_SHOW 1\n";
+        let history: String = ["usr_a", "usr_b"].iter()
+            .flat_map(|key| cycles.lines().map(move |line| format!("{key} {line}\n")))
+            .chain(["usr_a AUTO_RESET 1\n".to_owned(), "usr_b MANUAL_SHOW 1\nusr_b AUTO_RESET 1\n".to_owned()])
+            .collect();
+
+        // without unstick_after_hides, usr_a sticks at 3 shows and usr_b is shown again
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\n").unwrap();
+        fs::write(history_dir.join("a.history"), history.as_str()).unwrap();
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.files["a.vrcset"], FileRunStats { retained: 1, shown: 1, transactions: 2, ..FileRunStats::default() });
+
+        // with it, usr_a needs 6 shows so is reset, and usr_b has to be shown by hand
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\n").unwrap();
+        fs::write(history_dir.join("a.history"), history.as_str()).unwrap();
+        let mut config = Config::default();
+        config.unstick_after_hides = 2;
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.files["a.vrcset"], FileRunStats { removed: 1, transactions: 2, ..FileRunStats::default() });
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), "");
+    }
+
+    #[test]
+    fn test_run_all_stream_history() {
+        let moderations_dir = TestDir::new("stream-moderations");
+        let history_dir = TestDir::new("stream-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\nusr_b 005\r\n").unwrap();
+        // usr_a has stuck, and usr_c was reset before the threshold was lowered to where they would be shown again
+        let history = "usr_a MANUAL_SHOW\nusr_a AUTO_RESET\nusr_a MANUAL_SHOW\nusr_a AUTO_RESET\nusr_c MANUAL_SHOW\nusr_c AUTO_RESET\nusr_c MANUAL_SHOW\nusr_c AUTO_RESET\n";
+        fs::write(history_dir.join("a.history"), history).unwrap();
+
+        let mut config = Config::default();
+        config.auto_hide_threshold = 2;
+        config.stream_history_over_bytes = 100;
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.retained(), 1);
+        assert_eq!(stats.removed(), 1);
+        // only the users in the vrcset file were read, so usr_c is left for a run that reads everything
+        assert_eq!(stats.shown(), 0);
+
+        config.stream_history_over_bytes = 0;
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.shown(), 1);
+    }
+
+    #[test]
+    fn test_read_vrcset_keys_long_lines() {
+        let dir = TestDir::new("vrcset-keys-long-lines");
+        let path = dir.join("a.vrcset");
+        fs::write(path.as_path(), format!("usr_a 005\r\nusr_{} 004\r\nusr_b 004\r\n", "x".repeat(100))).unwrap();
+        assert_eq!(read_vrcset_keys(path.as_path(), 100).unwrap(), HashSet::from(["usr_a".to_owned(), "usr_b".to_owned()]));
+    }
+
+    #[test]
+    fn test_run_all_auto_hide_cooldown() {
+        let moderations_dir = TestDir::new("cooldown-moderations");
+        let history_dir = TestDir::new("cooldown-history");
+        let vrcset = "usr_a 005\r\nusr_b 005\r\nusr_c 005\r\n";
+        fs::write(moderations_dir.join("a.vrcset"), vrcset).unwrap();
+        // usr_a was reset inside the cooldown, usr_b long before it, and usr_c never
+        let now = current_timestamp();
+        let history = format!("usr_a MANUAL_SHOW {}\nusr_a AUTO_RESET {}\nusr_b MANUAL_SHOW {}\nusr_b AUTO_RESET {}\n", now - 200, now - 100, now - 7300, now - 7200);
+        fs::write(history_dir.join("a.history"), history).unwrap();
+
+        let mut config = Config::default();
+        config.auto_hide_threshold = 5;
+        config.auto_hide_cooldown_secs = 3600;
+        let options = Options { diff: true, ..Options::default() };
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &options, &mut LogFile::new(io::sink())).unwrap();
+        let diff = &stats.files["a.vrcset"].diff;
+        assert_eq!(diff.retained, ["usr_a"]);
+        assert_eq!(diff.removed, ["usr_b", "usr_c"]);
+        let history = fs::read_to_string(history_dir.join("a.history")).unwrap();
+        assert_eq!(history.lines().filter(|line| line.starts_with("usr_a AUTO_RESET")).count(), 1);
+
+        // the resets from that run start a cooldown of their own
+        fs::write(moderations_dir.join("a.vrcset"), vrcset).unwrap();
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &options, &mut LogFile::new(io::sink())).unwrap();
+        let diff = &stats.files["a.vrcset"].diff;
+        assert_eq!(diff.retained, ["usr_a", "usr_b", "usr_c"]);
+        assert!(diff.removed.is_empty());
+    }
+
+    #[test]
+    fn test_run_all_prune_hides() {
+        let moderations_dir = TestDir::new("prune-moderations");
+        let history_dir = TestDir::new("prune-history");
+        let vrcset = "usr_stale 004\r\nusr_recent 004\r\nusr_untimed 004\r\n";
+        fs::write(moderations_dir.join("a.vrcset"), vrcset).unwrap();
+        // usr_stale was last hidden outside the window, usr_recent inside it, and usr_untimed before timestamps
+        let now = current_timestamp();
+        let history = format!("usr_stale MANUAL_HIDE {}\nusr_recent MANUAL_HIDE {}\nusr_untimed MANUAL_HIDE\n", now - 40 * 60 * 60 * 24, now - 60 * 60 * 24);
+        fs::write(history_dir.join("a.history"), history).unwrap();
+
+        let mut config = Config::default();
+        config.prune_hides_after_days = 30;
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.pruned(), 1);
+        let expected: String = ["usr_recent", "usr_untimed"].iter()
+            .map(|key| moderation::Line::new((*key).to_owned(), moderation::Value::Hide).serialize_with(LineEnding::CrLf))
+            .collect();
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), expected);
+        let history = fs::read_to_string(history_dir.join("a.history")).unwrap();
+        assert!(history.lines().any(|line| line.starts_with("usr_stale AUTO_RESET ")), "{history}");
+
+        // pruning is off by default
+        fs::write(moderations_dir.join("a.vrcset"), vrcset).unwrap();
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.pruned(), 0);
+    }
+
+    #[test]
+    fn test_run_all_warmup_state_file() {
+        let moderations_dir = TestDir::new("warmup-moderations");
+        let history_dir = TestDir::new("warmup-history");
+        let state_path = history_dir.join("a.state.json");
+        let mut config = Config::default();
+        config.track_stats = false;
+        let run = |config: &Config| {
+            fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\n").unwrap();
+            run_all(config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap()
+        };
+
+        // without track_stats or a warm-up there's nothing to keep
+        run(&config);
+        assert!(!state_path.exists());
+
+        // a warm-up needs its runs counted, but only until it's over
+        config.warmup_runs = 1;
+        assert_eq!(run(&config).removed(), 0);
+        let state = fs::read_to_string(state_path.as_path()).unwrap();
+        assert_eq!(run(&config).removed(), 1);
+        assert_eq!(fs::read_to_string(state_path.as_path()).unwrap(), state);
+    }
+
+    #[test]
+    fn test_run_all_account_dirs() {
+        let moderations_dir = TestDir::new("accounts-moderations");
+        let history_dir = TestDir::new("accounts-history");
+        for account in ["usr_2", "usr_1"] {
+            fs::create_dir(moderations_dir.join(account)).unwrap();
+        }
+        // not an account folder
+        fs::create_dir(moderations_dir.join("backup")).unwrap();
+        fs::write(moderations_dir.join("backup").join("a.vrcset"), "usr_a 005\r\n").unwrap();
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\n").unwrap();
+        fs::write(moderations_dir.join("usr_1").join("a.vrcset"), "usr_a 005\r\n").unwrap();
+        fs::write(moderations_dir.join("usr_2").join("a.vrcset"), "usr_a 005\r\n").unwrap();
+        // only the first account has shown usr_a enough times to stick
+        fs::write(history_dir.join("usr_1.a.history"), "usr_a MANUAL_SHOW\nusr_a AUTO_RESET\nusr_a MANUAL_SHOW\nusr_a AUTO_RESET\n").unwrap();
+
+        let accounts: Vec<Option<String>> = moderations_dirs(moderations_dir.as_path()).unwrap().into_iter().map(|dir| dir.account).collect();
+        assert_eq!(accounts, [None, Some("usr_1".to_owned()), Some("usr_2".to_owned())]);
+
+        let log_dir = TestDir::new("accounts-log");
+        let mut log = LogFile::new(File::create(log_dir.join("log.txt")).unwrap());
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut log).unwrap();
+        assert_eq!(stats.files.keys().collect::<Vec<_>>(), ["a.vrcset", "usr_1/a.vrcset", "usr_2/a.vrcset"]);
+        // the log tells the files apart the same way
+        let log = fs::read_to_string(log_dir.join("log.txt")).unwrap();
+        assert!(log.contains("retained 1 shown user entries from usr_1/a.vrcset"), "{log}");
+        assert!(log.contains("removed 1 and retained 0 shown user entries from usr_2/a.vrcset"), "{log}");
+        assert_eq!(stats.files["usr_1/a.vrcset"].retained, 1);
+        assert_eq!(stats.files["usr_2/a.vrcset"].removed, 1);
+        assert_eq!(stats.files["a.vrcset"].removed, 1);
+        assert_eq!(fs::read_to_string(moderations_dir.join("usr_1").join("a.vrcset")).unwrap(), moderation::Line::new("usr_a".to_owned(), moderation::Value::Show).serialize());
+        assert_eq!(fs::read_to_string(moderations_dir.join("usr_2").join("a.vrcset")).unwrap(), "");
+        assert_eq!(fs::read_to_string(moderations_dir.join("backup").join("a.vrcset")).unwrap(), "usr_a 005\r\n");
+        for history in ["a.history", "usr_1.a.history", "usr_2.a.history"] {
+            assert!(history_dir.join(history).is_file(), "{history}");
+        }
+    }
+
+    #[test]
+    fn test_named_vrcset() {
+        assert_eq!(NamedVrcset::parse("a"), NamedVrcset { account: None, filename: "a.vrcset".to_owned() });
+        assert_eq!(NamedVrcset::parse("usr_1/a.vrcset"), NamedVrcset { account: Some("usr_1".to_owned()), filename: "a.vrcset".to_owned() });
+        assert_eq!(NamedVrcset::parse("usr_1\\a"), NamedVrcset { account: Some("usr_1".to_owned()), filename: "a.vrcset".to_owned() });
+
+        // the named file's history is the one a run records
+        let moderations_dir = TestDir::new("named-vrcset-moderations");
+        let history_dir = TestDir::new("named-vrcset-history");
+        fs::create_dir(moderations_dir.join("usr_1")).unwrap();
+        let named = NamedVrcset::parse("usr_1/a");
+        fs::write(named.vrcset_path(moderations_dir.as_path()), "usr_a 005\r\n").unwrap();
+        run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        let history = fs::read_to_string(named.history_path(history_dir.as_path()).unwrap()).unwrap();
+        assert!(history.contains("usr_a MANUAL_SHOW"), "{history}");
+        assert!(!NamedVrcset::parse("a").history_path(history_dir.as_path()).unwrap().exists());
+    }
+
+    #[test]
+    fn test_run_all_line_endings() {
+        let moderations_dir = TestDir::new("line-endings-moderations");
+        let history_dir = TestDir::new("line-endings-history");
+        fs::write(moderations_dir.join("crlf.vrcset"), "usr_a 004\r\nusr_b 005\r\nusr_c 004\r\n").unwrap();
+        fs::write(moderations_dir.join("lf.vrcset"), "usr_a 004\nusr_b 005\nusr_c 004\n").unwrap();
+
+        let mut config = Config::default();
+        for buffer_writes in [true, false] {
+            config.buffer_writes = buffer_writes;
+            run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+            for (filename, line_ending) in [("crlf.vrcset", LineEnding::CrLf), ("lf.vrcset", LineEnding::Lf)] {
+                let expected: String = ["usr_a", "usr_c"].iter()
+                    .map(|key| moderation::Line::new((*key).to_owned(), moderation::Value::Hide).serialize_with(line_ending))
+                    .collect();
+                assert_eq!(fs::read_to_string(moderations_dir.join(filename)).unwrap(), expected, "{filename}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_all_trailing_blank_lines() {
+        let moderations_dir = TestDir::new("trailing-blank-moderations");
+        let history_dir = TestDir::new("trailing-blank-history");
+        let log_dir = TestDir::new("trailing-blank-log");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 004\r\nusr_b 005\r\n\r\n   \r\n\r\n").unwrap();
+
+        let mut log = LogFile::new(File::create(log_dir.join("log.txt")).unwrap());
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut log).unwrap();
+        assert_eq!(stats.removed(), 1);
+        let expected = moderation::Line::new("usr_a".to_owned(), moderation::Value::Hide).serialize_with(LineEnding::CrLf);
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), expected);
+        let log = fs::read_to_string(log_dir.join("log.txt")).unwrap();
+        assert!(!log.contains("parse error"), "{log}");
+    }
+
+    #[test]
+    fn test_run_all_quarantines_long_lines() {
+        let moderations_dir = TestDir::new("quarantine-moderations");
+        let history_dir = TestDir::new("quarantine-history");
+        let long_line = format!("usr_{} 004", "x".repeat(100));
+        fs::write(moderations_dir.join("a.vrcset"), format!("usr_a 005\r\n{long_line}\r\nusr_b 004\r\n{long_line}")).unwrap();
+
+        let mut config = Config::default();
+        config.max_line_bytes = 100;
+        run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        let expected = moderation::Line::new("usr_b".to_owned(), moderation::Value::Hide).serialize_with(LineEnding::CrLf);
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), expected);
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset.quarantine")).unwrap(), format!("{long_line}\r\n{long_line}\n"));
+    }
+
+    #[test]
+    fn test_run_all_compacts_history() {
+        let moderations_dir = TestDir::new("run-all-compact-moderations");
+        let history_dir = TestDir::new("run-all-compact-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\n").unwrap();
+        fs::write(history_dir.join("a.history"), "usr_a MANUAL_SHOW 1\nusr_a AUTO_RESET 2\n".repeat(100)).unwrap();
+
+        let mut config = Config::default();
+        config.compact_history_over_bytes = 1000;
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.retained(), 1);
+        let history = fs::read_to_string(history_dir.join("a.history")).unwrap();
+        assert!(history.starts_with("usr_a MANUAL_SHOW 1\n"));
+        assert_eq!(history.lines().filter(|line| !transaction::is_checkpoint(line)).count(), 100 + 3); // 100 shows, the final reset, and this run's marker and show
+        assert_eq!(history.lines().filter(|line| transaction::is_checkpoint(line)).count(), 1);
+        assert!(history_dir.join("a.history.bak").is_file());
+    }
+
+    #[test]
+    fn test_run_all_pinned() {
+        let moderations_dir = TestDir::new("run-all-pinned-moderations");
+        let history_dir = TestDir::new("run-all-pinned-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\nusr_b 004\r\nusr_d 005\r\n").unwrap();
+        fs::write(history_dir.join("a.history"), "usr_c MANUAL_SHOW\nusr_c AUTO_RESET\n").unwrap();
+
+        let mut config = Config::default();
+        for user in ["usr_a", "usr_b", "usr_c"] {
+            config.apply_override(&format!("pin={user}")).unwrap();
+        }
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.files["a.vrcset"], FileRunStats { removed: 1, retained: 1, shown: 1, transactions: 5, ..FileRunStats::default() });
+
+        // usr_a is retained below the threshold, usr_b stays hidden, usr_c is brought back, and usr_d isn't pinned
+        let expected = [
+            moderation::Line::new("usr_a".to_owned(), moderation::Value::Show),
+            moderation::Line::new("usr_b".to_owned(), moderation::Value::Hide),
+            moderation::Line::new("usr_c".to_owned(), moderation::Value::Show),
+        ].iter().map(moderation::Line::serialize).collect::<String>();
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), expected);
+        let history = fs::read_to_string(history_dir.join("a.history")).unwrap();
+        assert!(!history.lines().any(|line| line.starts_with("usr_a AUTO_RESET")));
+    }
+
+    #[test]
+    fn test_run_all_auto_pin() {
+        let moderations_dir = TestDir::new("auto-pin-moderations");
+        let history_dir = TestDir::new("auto-pin-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 005\r\n").unwrap();
+        fs::write(history_dir.join("a.history"), "usr_a MANUAL_SHOW\nusr_a AUTO_RESET\nusr_a MANUAL_SHOW\nusr_a AUTO_RESET\n").unwrap();
+
+        let mut config = Config::default();
+        config.auto_pin_after_shows = 3;
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.files["a.vrcset"], FileRunStats { retained: 1, transactions: 2, ..FileRunStats::default() });
+        assert!(fs::read_to_string(history_dir.join("a.history")).unwrap().contains("usr_a AUTO_PIN "));
+
+        // a pinned user stays even once their count would no longer be enough, and isn't pinned twice
+        config.auto_hide_threshold = 10;
+        let stats = run_all(&config, moderations_dir.as_path(), history_dir.as_path(), &Options::default(), &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.files["a.vrcset"], FileRunStats { retained: 1, ..FileRunStats::default() });
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), moderation::Line::new("usr_a".to_owned(), moderation::Value::Show).serialize());
+    }
+
+    #[test]
+    fn test_run_all_dry_run() {
+        let moderations_dir = TestDir::new("run-all-dry-moderations");
+        let history_dir = TestDir::new("run-all-dry-history");
+        fs::write(moderations_dir.join("a.vrcset"), "usr_a 004\r\nusr_b 005\r\n").unwrap();
+
+        let options = Options {
+            dry_run: true,
+            ..Options::default()
+        };
+        let stats = run_all(&Config::default(), moderations_dir.as_path(), history_dir.as_path(), &options, &mut LogFile::new(io::sink())).unwrap();
+        assert_eq!(stats.removed(), 1);
+        assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), "usr_a 004\r\nusr_b 005\r\n");
+    }
+}
@@ -0,0 +1,157 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Per-file lifetime statistics. These are informational only: the transaction log is the source of truth, and a
+//! missing or corrupt stats file simply starts counting again from zero, which at worst restarts the warm-up period.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use crate::atomic;
+
+const RUNS: &str = "runs";
+const LAST_RUN: &str = "last_run";
+const LAST_REMOVED: &str = "last_removed";
+const LAST_RETAINED: &str = "last_retained";
+const LAST_ADDED: &str = "last_added";
+const TOTAL_REMOVED: &str = "total_removed";
+const TOTAL_RETENTIONS: &str = "total_retentions";
+/// what [`TOTAL_RETENTIONS`] used to be called
+const TOTAL_RETAINED: &str = "total_retained";
+const TOTAL_ADDED: &str = "total_added";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FileStats {
+    /// number of runs recorded so far
+    pub runs: u64,
+    /// unix timestamp in seconds of the most recent run
+    pub last_run: u64,
+    /// shown users reset in the most recent run
+    pub last_removed: u64,
+    /// shown users kept due to the threshold in the most recent run
+    pub last_retained: u64,
+    /// shown users re-added in the most recent run
+    pub last_added: u64,
+    pub total_removed: u64,
+    /// [`Self::last_retained`] summed over every run. A user kept for 100 runs counts 100 times, so this is not how many
+    /// distinct users ever stuck.
+    pub total_retentions: u64,
+    pub total_added: u64,
+}
+
+impl FileStats {
+    /// Load stats from disk, or `None` if the file does not exist
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>, Error> {
+        match fs::read_to_string(path) {
+            Ok(json) => Self::parse(&json).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    /// Parse the flat JSON object written by [`Self::serialize`]
+    fn parse(json: &str) -> Result<Self, Error> {
+        let body = json.trim()
+            .strip_prefix('{').and_then(|json| json.strip_suffix('}'))
+            .ok_or(Error::Syntax)?;
+        let mut stats = Self::default();
+        for field in body.split(',').map(str::trim).filter(|field| !field.is_empty()) {
+            let (key, value) = field.split_once(':').ok_or(Error::Syntax)?;
+            let key = key.trim().strip_prefix('"').and_then(|key| key.strip_suffix('"')).ok_or(Error::Syntax)?;
+            let value: u64 = value.trim().parse().map_err(|_| Error::Int)?;
+            match key {
+                RUNS => stats.runs = value,
+                LAST_RUN => stats.last_run = value,
+                LAST_REMOVED => stats.last_removed = value,
+                LAST_RETAINED => stats.last_retained = value,
+                LAST_ADDED => stats.last_added = value,
+                TOTAL_REMOVED => stats.total_removed = value,
+                TOTAL_RETENTIONS | TOTAL_RETAINED => stats.total_retentions = value,
+                TOTAL_ADDED => stats.total_added = value,
+                _ => {} // ignore unknown keys
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Record the results of a run
+    pub fn record_run(&mut self, timestamp: u64, removed: u32, retained: u32, added: u32) {
+        self.runs += 1;
+        self.last_run = timestamp;
+        self.last_removed = u64::from(removed);
+        self.last_retained = u64::from(retained);
+        self.last_added = u64::from(added);
+        self.total_removed += u64::from(removed);
+        self.total_retentions += u64::from(retained);
+        self.total_added += u64::from(added);
+    }
+
+    /// Replace the stats file atomically, so that a crash can't leave it half written
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), crate::Error> {
+        atomic::replace_file(path.as_ref(), |mut file| file.write_all(self.serialize().as_bytes()).map_err(crate::Error::Io))
+    }
+
+    fn serialize(&self) -> String {
+        format!("{{\"{RUNS}\":{},\"{LAST_RUN}\":{},\"{LAST_REMOVED}\":{},\"{LAST_RETAINED}\":{},\"{LAST_ADDED}\":{},\"{TOTAL_REMOVED}\":{},\"{TOTAL_RETENTIONS}\":{},\"{TOTAL_ADDED}\":{}}}\n",
+                self.runs,
+                self.last_run,
+                self.last_removed,
+                self.last_retained,
+                self.last_added,
+                self.total_removed,
+                self.total_retentions,
+                self.total_added)
+    }
+}
+
+#[allow(dead_code)] // lint misses usage in debug printing this error
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Syntax,
+    Int,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn test_stats_round_trip() {
+        let mut expected = FileStats::default();
+        expected.record_run(1700000000, 3, 2, 1);
+        expected.record_run(1700000100, 4, 2, 0);
+        let actual = FileStats::parse(&expected.serialize()).unwrap();
+        assert_eq!(actual, expected);
+        assert_eq!(actual.runs, 2);
+        assert_eq!(actual.total_removed, 7);
+        assert_eq!(actual.last_added, 0);
+    }
+
+    #[test]
+    fn test_stats_corrupt() {
+        assert!(FileStats::parse("{\"total_removed\":").is_err());
+    }
+
+    #[test]
+    fn test_stats_renamed_key() {
+        assert_eq!(FileStats::parse("{\"total_retained\":5}").unwrap().total_retentions, 5);
+    }
+
+    #[test]
+    fn test_stats_save() {
+        let dir = TestDir::new("stats-save");
+        let path = dir.join("a.state.json");
+        let mut expected = FileStats::default();
+        expected.record_run(1700000000, 3, 2, 1);
+        expected.save(path.as_path()).unwrap();
+        expected.record_run(1700000100, 4, 2, 0);
+        expected.save(path.as_path()).unwrap();
+        assert_eq!(FileStats::load(path.as_path()).unwrap(), Some(expected));
+        assert!(!dir.join("a.state.json.tmp").exists());
+    }
+}
@@ -0,0 +1,65 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Fixtures shared by the unit tests
+
+use std::{env, fs, process};
+use std::fs::File;
+use std::ops::Deref;
+use std::path::PathBuf;
+
+/// An empty directory for one test, deleted along with everything in it when dropped
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// `name` must be unique among the tests, as they run in parallel
+    pub fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("hooligan-test-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(dir.as_path());
+        fs::create_dir_all(dir.as_path()).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for TestDir {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        // best effort; a failure here shouldn't hide the result of the test
+        let _ = fs::remove_dir_all(self.0.as_path());
+    }
+}
+
+/// A file opened for reading in a [`TestDir`] of its own, which is deleted once the file is closed
+pub struct TestFile {
+    // declared first so it's closed before the directory is deleted
+    file: File,
+    _dir: TestDir,
+}
+
+impl TestFile {
+    pub fn new(name: &str, contents: &str) -> Self {
+        let dir = TestDir::new(name);
+        let path = dir.join(format!("{name}.history"));
+        fs::write(path.as_path(), contents).unwrap();
+        Self {
+            file: File::open(path).unwrap(),
+            _dir: dir,
+        }
+    }
+}
+
+impl Deref for TestFile {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        &self.file
+    }
+}
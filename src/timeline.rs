@@ -0,0 +1,191 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! Chronological view of a single .vrcset file, combining log lines with history transactions
+
+use std::borrow::Cow;
+
+use chrono::DateTime;
+
+use crate::json;
+use crate::transaction::Transaction;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Source {
+    Log,
+    History,
+}
+
+impl Source {
+    const fn serialize(&self) -> &'static str {
+        match self {
+            Self::Log => "log",
+            Self::History => "history",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Event {
+    /// unix timestamp in seconds
+    pub timestamp: u64,
+    pub source: Source,
+    pub text: String,
+}
+
+#[derive(Default)]
+pub struct Timeline {
+    events: Vec<Event>,
+}
+
+impl Timeline {
+    /// Add a log line if it has a timestamp and mentions the file logs call `display_name`, `<ACCOUNT>/<FILENAME>` for
+    /// one in an account folder. Both the text and JSON `log_format`s are understood, as the setting may have changed
+    /// between runs.
+    pub fn add_log_line(&mut self, line: &str, display_name: &str) {
+        if let Some((timestamp, message)) = parse_log_line(line) {
+            if mentions(&message, display_name) {
+                self.events.push(Event { timestamp, source: Source::Log, text: message.into_owned() });
+            }
+        }
+    }
+
+    /// Add a transaction. Transactions recorded before timestamps existed can't be placed, so they are skipped.
+    pub fn add_transaction(&mut self, transaction: &Transaction) {
+        if let Some(timestamp) = transaction.timestamp {
+            let text = transaction.serialize().trim_end().to_owned();
+            self.events.push(Event { timestamp, source: Source::History, text });
+        }
+    }
+
+    /// Events in chronological order. Events with the same timestamp keep the order they were added in.
+    pub fn into_events(mut self) -> Vec<Event> {
+        self.events.sort_by_key(|event| event.timestamp);
+        self.events
+    }
+
+    pub fn to_human(events: &[Event]) -> String {
+        events.iter()
+            .map(|event| format!("{} {}: {}\n", event.timestamp, event.source.serialize(), event.text))
+            .collect()
+    }
+
+    pub fn to_json(events: &[Event]) -> String {
+        let events: Vec<String> = events.iter()
+            .map(|event| format!("{{\"timestamp\":{},\"source\":{},\"text\":{}}}",
+                                 event.timestamp,
+                                 json::string(event.source.serialize()),
+                                 json::string(&event.text)))
+            .collect();
+        format!("[{}]\n", events.join(","))
+    }
+}
+
+/// true if `message` names the file `display_name` on its own, rather than as the end of a longer name such as the same
+/// filename in an account folder
+fn mentions(message: &str, display_name: &str) -> bool {
+    message.match_indices(display_name).any(|(index, _)| {
+        !message[..index].ends_with(|c: char| c.is_alphanumeric() || matches!(c, '/' | '\\' | '_' | '-' | '.'))
+    })
+}
+
+/// Split a `<timestamp>: <message>` log line, or a `{"ts":<timestamp>,"level":<level>,"msg":<message>}` JSON log line.
+/// The timestamp may be unix seconds or RFC 3339, depending on the `log_timestamp` setting when the line was written.
+fn parse_log_line(line: &str) -> Option<(u64, Cow<'_, str>)> {
+    if let Some(fields) = line.strip_prefix("{\"ts\":") {
+        return parse_json_log_line(fields);
+    }
+    let (timestamp, message) = line.split_once(": ")?;
+    Some((parse_timestamp(timestamp)?, Cow::Borrowed(message)))
+}
+
+/// `fields` is a JSON log line with the leading `{"ts":` already removed
+fn parse_json_log_line(fields: &str) -> Option<(u64, Cow<'_, str>)> {
+    let (timestamp, fields) = match json::parse_string(fields) {
+        Some((timestamp, fields)) => (parse_timestamp(&timestamp)?, fields.strip_prefix(',')?),
+        None => {
+            let (timestamp, fields) = fields.split_once(',')?;
+            (timestamp.parse().ok()?, fields)
+        }
+    };
+    let (_, fields) = json::parse_string(fields.strip_prefix("\"level\":")?)?;
+    let (message, fields) = json::parse_string(fields.strip_prefix(",\"msg\":")?)?;
+    (fields == "}").then_some((timestamp, Cow::Owned(message)))
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    match timestamp.parse() {
+        Ok(timestamp) => Some(timestamp),
+        Err(_) => u64::try_from(DateTime::parse_from_rfc3339(timestamp).ok()?.timestamp()).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Value;
+
+    #[test]
+    fn test_timeline_order() {
+        let mut timeline = Timeline::default();
+        timeline.add_log_line("200: removed 1 and retained 0 shown user entries from a.vrcset", "a.vrcset");
+        timeline.add_log_line("200: removed 0 and retained 0 shown user entries from b.vrcset", "a.vrcset");
+        timeline.add_log_line("not a timestamped line a.vrcset", "a.vrcset");
+        timeline.add_log_line("100: processing a.vrcset", "a.vrcset");
+        let mut transaction = Transaction::new("usr_a".to_string(), Value::AutoReset, 200);
+        transaction.timestamp = Some(200);
+        timeline.add_transaction(&transaction);
+        transaction.timestamp = None;
+        timeline.add_transaction(&transaction);
+
+        let events = timeline.into_events();
+        assert_eq!(Timeline::to_human(&events), "100 log: processing a.vrcset\n\
+                                                 200 log: removed 1 and retained 0 shown user entries from a.vrcset\n\
+                                                 200 history: usr_a AUTO_RESET 200\n");
+        assert_eq!(Timeline::to_json(&events[..1]), "[{\"timestamp\":100,\"source\":\"log\",\"text\":\"processing a.vrcset\"}]\n");
+    }
+
+    #[test]
+    fn test_timeline_account_log_lines() {
+        let lines = [
+            "100: processing a.vrcset",
+            "101: processing usr_x/a.vrcset",
+            "102: processing usr_y/a.vrcset",
+            "103: processing ba.vrcset",
+            "104: removed 1 and retained 0 shown user entries from \"a.vrcset\"",
+        ];
+        let timestamps = |display_name| {
+            let mut timeline = Timeline::default();
+            for line in lines {
+                timeline.add_log_line(line, display_name);
+            }
+            timeline.into_events().iter().map(|event| event.timestamp).collect::<Vec<_>>()
+        };
+        assert_eq!(timestamps("a.vrcset"), [100, 104]);
+        assert_eq!(timestamps("usr_x/a.vrcset"), [101]);
+    }
+
+    #[test]
+    fn test_timeline_iso_log_lines() {
+        let mut timeline = Timeline::default();
+        timeline.add_log_line("2025-01-15T14:03:22-05:00: processing a.vrcset", "a.vrcset");
+        timeline.add_log_line("1736967801: processing a.vrcset", "a.vrcset");
+        let events = timeline.into_events();
+        assert_eq!(events.iter().map(|event| event.timestamp).collect::<Vec<_>>(), [1_736_967_801, 1_736_967_802]);
+    }
+
+    #[test]
+    fn test_timeline_json_log_lines() {
+        let mut timeline = Timeline::default();
+        timeline.add_log_line("{\"ts\":\"2025-01-15T14:03:22-05:00\",\"level\":\"info\",\"msg\":\"removed 1 and retained 0 shown user entries from \\\"a.vrcset\\\"\"}", "a.vrcset");
+        timeline.add_log_line("{\"ts\":1736967801,\"level\":\"info\",\"msg\":\"processing a.vrcset\"}", "a.vrcset");
+        timeline.add_log_line("{\"ts\":1736967801,\"level\":\"info\",\"msg\":\"processing b.vrcset\"}", "a.vrcset");
+        timeline.add_log_line("{\"ts\":1736967801,\"level\":\"info\",\"msg\":\"truncated a.vrcset", "a.vrcset");
+        timeline.add_log_line("1736967803: processing a.vrcset", "a.vrcset");
+        let events = timeline.into_events();
+        assert_eq!(Timeline::to_human(&events), "1736967801 log: processing a.vrcset\n\
+                                                 1736967802 log: removed 1 and retained 0 shown user entries from \"a.vrcset\"\n\
+                                                 1736967803 log: processing a.vrcset\n");
+    }
+}
@@ -0,0 +1,175 @@
+// This file is part of hooligan and is licenced under the GNU GPL v3.0.
+// See LICENSE file for full text.
+// Copyright © 2024 Michael Ripley
+
+//! End-to-end runs of the hooligan binary against fixture .vrcset files. Linux only, as that's where pointing `HOME`
+//! somewhere else is enough to keep the runs away from the real config, history, and logs.
+
+#![cfg(target_os = "linux")]
+
+use std::{env, fs, process};
+use std::fs::File;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use hooligan::config::Config;
+use hooligan::local_player_moderations::{Line, Value};
+use hooligan::transaction;
+
+/// An empty directory for one test, deleted along with everything in it when dropped
+struct TestDir(PathBuf);
+
+impl TestDir {
+    /// `name` must be unique among the tests, as they run in parallel
+    fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("hooligan-cli-test-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(dir.as_path());
+        fs::create_dir_all(dir.as_path()).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for TestDir {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(self.0.as_path());
+    }
+}
+
+/// Run hooligan with `home` standing in for the user's home directory, so its config, history, and logs all end up in
+/// there rather than the real ones
+fn hooligan(home: &TestDir) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_hooligan"));
+    command.env("HOME", home.as_path())
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("XDG_DATA_HOME")
+        .env_remove("HOOLIGAN_MODERATIONS_DIR");
+    command
+}
+
+fn run(command: &mut Command) -> Output {
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    output
+}
+
+/// everything hooligan logged with `home` as the home directory
+fn read_logs(home: &TestDir) -> String {
+    let log_dir = home.join(".local").join("share").join("hooligan").join("logs");
+    fs::read_dir(log_dir).unwrap()
+        .map(|dir_entry| fs::read_to_string(dir_entry.unwrap().path()).unwrap())
+        .collect()
+}
+
+/// the .vrcset fixture: usr_a is shown with no history, so a run removes them, and usr_b is hidden
+const FIXTURE: &str = "usr_a 005\r\nusr_b 004\r\n";
+
+/// what a run leaves of [`FIXTURE`]
+fn processed_fixture() -> String {
+    Line::new("usr_b".to_owned(), Value::Hide).serialize()
+}
+
+#[test]
+fn test_moderations_dir_var() {
+    let home = TestDir::new("moderations-dir-var-home");
+    let moderations_dir = TestDir::new("moderations-dir-var-moderations");
+    fs::write(moderations_dir.join("a.vrcset"), FIXTURE).unwrap();
+
+    run(hooligan(&home).env("HOOLIGAN_MODERATIONS_DIR", moderations_dir.as_path()).arg("--no-launch"));
+    assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), processed_fixture());
+}
+
+#[test]
+fn test_moderations_dir_arg() {
+    let home = TestDir::new("moderations-dir-arg-home");
+    let moderations_dir = TestDir::new("moderations-dir-arg-moderations");
+    let ignored_dir = TestDir::new("moderations-dir-arg-ignored");
+    fs::write(moderations_dir.join("a.vrcset"), FIXTURE).unwrap();
+    fs::write(ignored_dir.join("a.vrcset"), FIXTURE).unwrap();
+
+    // the argument wins over the environment variable
+    run(hooligan(&home)
+        .env("HOOLIGAN_MODERATIONS_DIR", ignored_dir.as_path())
+        .arg("--moderations-dir")
+        .arg(moderations_dir.as_path())
+        .arg("--no-launch"));
+    assert_eq!(fs::read_to_string(moderations_dir.join("a.vrcset")).unwrap(), processed_fixture());
+    assert_eq!(fs::read_to_string(ignored_dir.join("a.vrcset")).unwrap(), FIXTURE);
+}
+
+#[test]
+fn test_moderations_dir_arg_not_found() {
+    let home = TestDir::new("moderations-dir-not-found-home");
+    let missing_dir = home.join("missing");
+
+    run(hooligan(&home).arg("--moderations-dir").arg(missing_dir.as_path()).arg("--no-launch"));
+    assert!(!missing_dir.exists());
+    let log = read_logs(&home);
+    assert!(log.contains("ModerationsPathNotFound"), "{log}");
+}
+
+#[test]
+fn test_moderations_dir_is_file() {
+    let home = TestDir::new("moderations-dir-is-file-home");
+    let file = home.join("a.vrcset");
+    fs::write(file.as_path(), FIXTURE).unwrap();
+
+    run(hooligan(&home).env("HOOLIGAN_MODERATIONS_DIR", file.as_path()).arg("--no-launch"));
+    assert_eq!(fs::read_to_string(file.as_path()).unwrap(), FIXTURE);
+    let log = read_logs(&home);
+    assert!(log.contains("ModerationsPathIsFile"), "{log}");
+    // whichever way the path was given, the log says where to fix it
+    assert!(log.contains("HOOLIGAN_MODERATIONS_DIR") && log.contains("--moderations-dir"), "{log}");
+}
+
+#[test]
+fn test_status_history_too_large() {
+    let home = TestDir::new("status-history-too-large-home");
+    let history_dir = home.join(".local").join("share").join("hooligan").join("history");
+    fs::create_dir_all(history_dir.as_path()).unwrap();
+    fs::write(history_dir.join("big.history"), "usr_a MANUAL_SHOW 1\nusr_b MANUAL_SHOW 1\nusr_c MANUAL_SHOW 1\n").unwrap();
+    fs::write(history_dir.join("small.history"), "usr_d MANUAL_SHOW 1\n").unwrap();
+
+    // the oversized history is left out rather than failing the whole report
+    let output = run(hooligan(&home).args(["status", "--set", "max_history_keys=2", "--set", "auto_hide_threshold=1"]));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "small: usr_d shown 1 since 1\n");
+}
+
+#[test]
+fn test_reset_all() {
+    let home = TestDir::new("reset-all-home");
+    let moderations_dir = TestDir::new("reset-all-moderations");
+    let history_dir = home.join(".local").join("share").join("hooligan").join("history");
+    fs::create_dir_all(history_dir.as_path()).unwrap();
+    // usr_a was auto reset, so is back to default but still has a show count
+    let history = "usr_a MANUAL_SHOW 1\nusr_a AUTO_RESET 2\nusr_b MANUAL_SHOW 1\nusr_b MANUAL_SHOW 2\nusr_c MANUAL_HIDE 3\n";
+    fs::write(history_dir.join("a.history"), history).unwrap();
+
+    let reset_all = |home: &TestDir, dry_run: bool| {
+        let mut command = hooligan(home);
+        command.env("HOOLIGAN_MODERATIONS_DIR", moderations_dir.as_path()).args(["--reset-all", "--yes"]);
+        if dry_run {
+            command.arg("--dry-run");
+        }
+        run(&mut command);
+    };
+
+    reset_all(&home, true);
+    assert_eq!(fs::read_to_string(history_dir.join("a.history")).unwrap(), history);
+
+    reset_all(&home, false);
+    let file = File::open(history_dir.join("a.history")).unwrap();
+    let counts = transaction::read_log(&file, &Config::default(), hooligan::current_timestamp(), |_| {}).unwrap();
+    assert_eq!(counts.len(), 3);
+    for (key, show_hide_count) in counts {
+        assert!(show_hide_count.is_default() && show_hide_count.count() == 0, "{key}: {show_hide_count:?}");
+    }
+}